@@ -0,0 +1,974 @@
+// Assembler for Octo source (.8o), so programs can run without a separate
+// compile step.
+//
+// Supported: labels, :alias, :const, :calc, :macro, :byte, :org, structured
+// if/then, if/begin/else/end, loop/while/again, and every instruction the
+// emulator implements. Like Octo, 0x200 holds a jump to main unless main is
+// the first thing in the program.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+const PROGRAM_START: usize = 0x200;
+const MAX_ADDRESS: usize = 0xFFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+// Octo tokens are separated by whitespace, # starts a comment
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (index, c) in line.char_indices().chain([(line.len(), ' ')]) {
+            if c == '#' && start.is_none() {
+                break;
+            }
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(index),
+                (true, Some(from)) => {
+                    tokens.push_back(Token {
+                        text: line[from..index].to_string(),
+                        line: line_index + 1,
+                        column: line[..from].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    // low 12 bits of the instruction at the address
+    Nnn,
+    // the whole 16-bit word at the address
+    Word,
+}
+
+#[derive(Debug)]
+enum Block {
+    // address of the jump over the body
+    If {
+        jump: usize,
+        token: Token,
+    },
+    Else {
+        jump: usize,
+        token: Token,
+    },
+    Loop {
+        start: usize,
+        whiles: Vec<usize>,
+        token: Token,
+    },
+}
+
+pub fn assemble(source: &str, file: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler {
+        file,
+        tokens: tokenize(source),
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        main_jump: true,
+        last: Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+        },
+        expansions: 0,
+    };
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+struct Assembler<'a> {
+    file: &'a str,
+    tokens: VecDeque<Token>,
+    // bytes from 0x200 up
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    // forward references, patched once every label is known
+    fixups: Vec<(usize, Fixup, Token)>,
+    blocks: Vec<Block>,
+    // true while 0x200 is reserved for the jump to main
+    main_jump: bool,
+    // most recent token, for errors at the end of the input
+    last: Token,
+    // guards against macros that expand forever
+    expansions: usize,
+}
+
+impl Assembler<'_> {
+    fn error(&self, token: &Token, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            file: self.file.to_string(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(self.error(&self.last, "unexpected end of file")),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(
+                &token,
+                format!("expected '{}', found '{}'", text, token.text),
+            ));
+        }
+        Ok(token)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AssembleError> {
+        if self.here > MAX_ADDRESS {
+            return Err(self.error(&self.last, "program is larger than 64 KiB"));
+        }
+        let offset = self.here - PROGRAM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn inst(&mut self, opcode: u16) -> Result<(), AssembleError> {
+        self.emit((opcode >> 8) as u8)?;
+        self.emit(opcode as u8)
+    }
+
+    fn patch(&mut self, address: usize, fixup: Fixup, value: usize) {
+        let offset = address - PROGRAM_START;
+        match fixup {
+            Fixup::Nnn => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | ((value >> 8) & 0xF) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            Fixup::Word => {
+                self.rom[offset] = (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+        }
+    }
+
+    // structured control flow jumps with 1nnn, so its targets need 12 bits
+    fn jump_target(&self, token: &Token, address: usize) -> Result<usize, AssembleError> {
+        if address > 0xFFF {
+            return Err(self.error(
+                token,
+                format!("'{}' jumps to {:#X}, above 0xFFF", token.text, address),
+            ));
+        }
+        Ok(address)
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        // the jump to main, dropped again if main turns out to be at 0x202
+        self.inst(0x1000)?;
+        while !self.tokens.is_empty() {
+            let token = self.next()?;
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.pop() {
+            let (Block::If { token, .. } | Block::Else { token, .. } | Block::Loop { token, .. }) =
+                block;
+            return Err(self.error(&token, format!("'{}' is never closed", token.text)));
+        }
+        if self.main_jump {
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or_else(|| self.error(&self.last, "program has no ': main' label"))?;
+            if main > 0xFFF {
+                return Err(self.error(&self.last, "'main' is above 0xFFF"));
+            }
+            self.patch(PROGRAM_START, Fixup::Nnn, main);
+        }
+        for (address, fixup, token) in std::mem::take(&mut self.fixups) {
+            let value = *self
+                .labels
+                .get(&token.text)
+                .ok_or_else(|| self.error(&token, format!("undefined name '{}'", token.text)))?;
+            if matches!(fixup, Fixup::Nnn) && value > 0xFFF {
+                return Err(self.error(
+                    &token,
+                    format!("'{}' is above 0xFFF, use i := long", token.text),
+                ));
+            }
+            self.patch(address, fixup, value);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        if let Some(register) = self.register(&token) {
+            return self.register_statement(register);
+        }
+        if let Some(number) = parse_number(&token.text) {
+            let byte = self.byte_range(&token, number)?;
+            return self.emit(byte);
+        }
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                if name.text == "main" && self.main_jump && self.here == PROGRAM_START + 2 {
+                    // main comes first, so execution can start there directly
+                    self.rom.clear();
+                    self.here = PROGRAM_START;
+                    self.main_jump = false;
+                }
+                if self.labels.insert(name.text.clone(), self.here).is_some() {
+                    return Err(
+                        self.error(&name, format!("label '{}' is already defined", name.text))
+                    );
+                }
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let target = self.next()?;
+                let register = self
+                    .register(&target)
+                    .ok_or_else(|| self.error(&target, "expected a register"))?;
+                self.aliases.insert(name.text, register);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.next()?;
+                let value = self.constant(&value)?;
+                self.consts.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.expect("}")?;
+                self.consts.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    let open = self.next()?;
+                    let value = self.calc()?;
+                    self.expect("}")?;
+                    (open, value)
+                } else {
+                    let token = self.next()?;
+                    let value = self.constant(&token)?;
+                    (token, value)
+                };
+                let byte = self.byte_range(&value.0, value.1.floor() as i64)?;
+                self.emit(byte)?;
+            }
+            ":org" => {
+                let address = self.next()?;
+                let value = self.constant(&address)?.floor() as i64;
+                if !(PROGRAM_START as i64..=MAX_ADDRESS as i64).contains(&value) {
+                    return Err(self.error(&address, "address is outside program memory"));
+                }
+                self.here = value as usize;
+            }
+            ":macro" => self.define_macro()?,
+            // debugger hints for Octo's own IDE, nothing to emit
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.inst(0x00EE)?,
+            "clear" => self.inst(0x00E0)?,
+            "exit" => self.inst(0x00FD)?,
+            "lores" => self.inst(0x00FE)?,
+            "hires" => self.inst(0x00FF)?,
+            "scroll-right" => self.inst(0x00FB)?,
+            "scroll-left" => self.inst(0x00FC)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.inst(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.inst(0x00D0 | n)?;
+            }
+            "audio" => self.inst(0xF002)?,
+            "plane" => {
+                let token = self.next()?;
+                let n = self.constant(&token)?.floor() as i64;
+                if !(0..=3).contains(&n) {
+                    return Err(self.error(&token, "plane mask must be 0-3"));
+                }
+                self.inst(0xF001 | (n as u16) << 8)?;
+            }
+            "bcd" => self.x_op(0xF033)?,
+            "saveflags" => self.x_op(0xF075)?,
+            "loadflags" => self.x_op(0xF085)?,
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.expect_register()?;
+                    let kind = if token.text == "save" { 2 } else { 3 };
+                    self.inst(0x5000 | (x as u16) << 8 | (y as u16) << 4 | kind)?;
+                } else {
+                    let kind = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.inst(0xF000 | (x as u16) << 8 | kind)?;
+                }
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble()?;
+                self.inst(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)?;
+            }
+            "jump" => self.address_op(0x1000)?,
+            "jump0" => self.address_op(0xB000)?,
+            "native" => self.address_op(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()? as u16;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.inst(0xF000 | x << 8 | low)?;
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let else_jump = self.here;
+                    self.inst(0x1000)?;
+                    let here = self.jump_target(&token, self.here)?;
+                    self.patch(jump, Fixup::Nnn, here);
+                    self.blocks.push(Block::Else {
+                        jump: else_jump,
+                        token,
+                    });
+                }
+                _ => return Err(self.error(&token, "'else' without 'if ... begin'")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. } | Block::Else { jump, .. }) => {
+                    let here = self.jump_target(&token, self.here)?;
+                    self.patch(jump, Fixup::Nnn, here);
+                }
+                _ => return Err(self.error(&token, "'end' without 'if ... begin'")),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                whiles: Vec::new(),
+                token,
+            }),
+            "while" => {
+                if !self
+                    .blocks
+                    .iter()
+                    .any(|block| matches!(block, Block::Loop { .. }))
+                {
+                    return Err(self.error(&token, "'while' outside of a loop"));
+                }
+                // skip the exit jump while the condition holds
+                self.condition(true)?;
+                let jump = self.here;
+                self.inst(0x1000)?;
+                if let Some(Block::Loop { whiles, .. }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    whiles.push(jump);
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, whiles, .. }) => {
+                    let start = self.jump_target(&token, start)?;
+                    self.inst(0x1000 | start as u16)?;
+                    let here = self.jump_target(&token, self.here)?;
+                    for jump in whiles {
+                        self.patch(jump, Fixup::Nnn, here);
+                    }
+                }
+                _ => return Err(self.error(&token, "'again' without 'loop'")),
+            },
+            name if self.macros.contains_key(name) => self.expand_macro(token)?,
+            name if name.starts_with(':') => {
+                return Err(self.error(&token, format!("unknown directive '{}'", name)));
+            }
+            // any other name calls the subroutine it labels
+            _ => {
+                self.check_name(&token)?;
+                self.address(0x2000, &token)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+        let digit = token.text.strip_prefix(['v', 'V'])?;
+        match u8::from_str_radix(digit, 16) {
+            Ok(n) if digit.len() == 1 => Some(n),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register(&token).ok_or_else(|| {
+            self.error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    // names can't shadow registers or numbers
+    fn check_name(&self, token: &Token) -> Result<(), AssembleError> {
+        if self.register(token).is_some()
+            || parse_number(&token.text).is_some()
+            || token.text == "i"
+            || token.text.is_empty()
+        {
+            return Err(self.error(token, format!("'{}' can't be used as a name", token.text)));
+        }
+        Ok(())
+    }
+
+    // a number, a constant or an already defined label
+    fn constant(&self, token: &Token) -> Result<f64, AssembleError> {
+        if let Some(number) = parse_number(&token.text) {
+            return Ok(number as f64);
+        }
+        if let Some(&value) = self.consts.get(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&address) = self.labels.get(&token.text) {
+            return Ok(address as f64);
+        }
+        Err(self.error(token, format!("expected a number, found '{}'", token.text)))
+    }
+
+    fn byte_range(&self, token: &Token, value: i64) -> Result<u8, AssembleError> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(token, format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        let value = self.constant(&token)?.floor() as i64;
+        self.byte_range(&token, value)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        let value = self.constant(&token)?.floor() as i64;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(&token, format!("{} doesn't fit in a nibble", value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn x_op(&mut self, opcode: u16) -> Result<(), AssembleError> {
+        let x = self.expect_register()? as u16;
+        self.inst(opcode | x << 8)
+    }
+
+    fn address_op(&mut self, opcode: u16) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        self.address(opcode, &token)
+    }
+
+    // an instruction with a 12-bit address operand, labels may be defined later
+    fn address(&mut self, opcode: u16, token: &Token) -> Result<(), AssembleError> {
+        let value = match self.constant(token) {
+            Ok(value) => value.floor() as i64,
+            Err(_) => {
+                self.check_name(token)?;
+                self.fixups.push((self.here, Fixup::Nnn, token.clone()));
+                0
+            }
+        };
+        if !(0..=0xFFF).contains(&value) {
+            return Err(self.error(token, format!("{:#X} doesn't fit in 12 bits", value)));
+        }
+        self.inst(opcode | value as u16)
+    }
+
+    fn i_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {}
+            "+=" => return self.x_op(0xF01E),
+            _ => return Err(self.error(&op, format!("expected ':=' or '+=', found '{}'", op.text))),
+        }
+        let token = self.next()?;
+        match token.text.as_str() {
+            "hex" => self.x_op(0xF029),
+            "bighex" => self.x_op(0xF030),
+            "long" => {
+                let target = self.next()?;
+                self.inst(0xF000)?;
+                let value = match self.constant(&target) {
+                    Ok(value) => value.floor() as i64,
+                    Err(_) => {
+                        self.check_name(&target)?;
+                        self.fixups.push((self.here, Fixup::Word, target.clone()));
+                        0
+                    }
+                };
+                if !(0..=0xFFFF).contains(&value) {
+                    return Err(self.error(&target, format!("{:#X} doesn't fit in 16 bits", value)));
+                }
+                self.inst(value as u16)
+            }
+            _ => self.address(0xA000, &token),
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AssembleError> {
+        let x = x as u16;
+        let op = self.next()?;
+        let operand = self.next()?;
+        let y = self.register(&operand).map(|y| y as u16);
+        let alu = |low: u16| -> Option<u16> { y.map(|y| 0x8000 | x << 8 | y << 4 | low) };
+        let opcode = match op.text.as_str() {
+            ":=" => match (y, operand.text.as_str()) {
+                (Some(_), _) => alu(0x0),
+                (None, "random") => {
+                    let mask = self.byte()? as u16;
+                    Some(0xC000 | x << 8 | mask)
+                }
+                (None, "key") => Some(0xF00A | x << 8),
+                (None, "delay") => Some(0xF007 | x << 8),
+                (None, _) => {
+                    let value = self.constant(&operand)?.floor() as i64;
+                    Some(0x6000 | x << 8 | self.byte_range(&operand, value)? as u16)
+                }
+            },
+            "+=" if y.is_none() => {
+                let value = self.constant(&operand)?.floor() as i64;
+                Some(0x7000 | x << 8 | self.byte_range(&operand, value)? as u16)
+            }
+            "-=" if y.is_none() => {
+                let value = self.constant(&operand)?.floor() as i64;
+                let byte = self.byte_range(&operand, value)?;
+                Some(0x7000 | x << 8 | byte.wrapping_neg() as u16)
+            }
+            "|=" => alu(0x1),
+            "&=" => alu(0x2),
+            "^=" => alu(0x3),
+            "+=" => alu(0x4),
+            "-=" => alu(0x5),
+            ">>=" => alu(0x6),
+            "=-" => alu(0x7),
+            "<<=" => alu(0xE),
+            _ => return Err(self.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+        let opcode = opcode.ok_or_else(|| self.error(&operand, "expected a register"))?;
+        self.inst(opcode)
+    }
+
+    fn if_statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        // peek past the condition to see which form this is
+        let mut condition_len = 2;
+        if !matches!(
+            self.tokens.get(1).map(|t| t.text.as_str()),
+            Some("key" | "-key")
+        ) {
+            condition_len = 3;
+        }
+        match self.tokens.get(condition_len).map(|t| t.text.as_str()) {
+            Some("then") => {
+                self.condition(false)?;
+                self.next()?;
+            }
+            Some("begin") => {
+                // jump over the body unless the condition holds
+                self.condition(true)?;
+                self.next()?;
+                let jump = self.here;
+                self.inst(0x1000)?;
+                self.blocks.push(Block::If { jump, token });
+            }
+            _ => return Err(self.error(&token, "expected 'then' or 'begin' after the condition")),
+        }
+        Ok(())
+    }
+
+    // Emits code that skips the next instruction when the condition is false,
+    // or when it is true if negated. Comparisons use vF as a scratch register.
+    fn condition(&mut self, negated: bool) -> Result<(), AssembleError> {
+        let x = self.expect_register()? as u16;
+        let op = self.next()?;
+        let mut text = op.text.as_str();
+        if negated {
+            text = match text {
+                "==" => "!=",
+                "!=" => "==",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                "key" => "-key",
+                "-key" => "key",
+                other => other,
+            };
+        }
+        let text = text.to_string();
+        match text.as_str() {
+            "key" => return self.inst(0xE0A1 | x << 8),
+            "-key" => return self.inst(0xE09E | x << 8),
+            _ => {}
+        }
+        let operand = self.next()?;
+        let y = self.register(&operand).map(|y| y as u16);
+        let value = match y {
+            Some(_) => 0,
+            None => {
+                let value = self.constant(&operand)?.floor() as i64;
+                self.byte_range(&operand, value)? as u16
+            }
+        };
+        match (text.as_str(), y) {
+            ("==", Some(y)) => self.inst(0x9000 | x << 8 | y << 4),
+            ("==", None) => self.inst(0x4000 | x << 8 | value),
+            ("!=", Some(y)) => self.inst(0x5000 | x << 8 | y << 4),
+            ("!=", None) => self.inst(0x3000 | x << 8 | value),
+            (">" | "<" | ">=" | "<=", _) => {
+                match y {
+                    Some(y) => self.inst(0x8F00 | y << 4)?,
+                    None => self.inst(0x6F00 | value)?,
+                }
+                let (sub, skip) = match text.as_str() {
+                    ">" => (0x5, 0x3F01),
+                    "<" => (0x7, 0x3F01),
+                    ">=" => (0x7, 0x4F01),
+                    _ => (0x5, 0x4F01),
+                };
+                self.inst(0x8F00 | x << 4 | sub)?;
+                self.inst(skip)
+            }
+            _ => Err(self.error(&op, format!("unknown comparison '{}'", op.text))),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, token: Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > 10_000 {
+            return Err(self.error(&token, "macro expansion doesn't terminate"));
+        }
+        let definition = self.macros[&token.text].clone();
+        let mut args = HashMap::new();
+        for param in &definition.params {
+            let arg = self.next()?;
+            args.insert(param.clone(), arg.text);
+        }
+        for body_token in definition.body.into_iter().rev() {
+            let text = args
+                .get(&body_token.text)
+                .cloned()
+                .unwrap_or(body_token.text);
+            self.tokens.push_front(Token {
+                text,
+                line: body_token.line,
+                column: body_token.column,
+            });
+        }
+        Ok(())
+    }
+
+    // :calc expressions: every binary operator has the same precedence and
+    // they group right to left, as in Octo
+    fn calc(&mut self) -> Result<f64, AssembleError> {
+        let lhs = self.calc_term()?;
+        let Some(op) = self
+            .peek()
+            .filter(|op| is_binary_op(op))
+            .map(str::to_string)
+        else {
+            return Ok(lhs);
+        };
+        let op_token = self.next()?;
+        let rhs = self.calc()?;
+        let (a, b) = (lhs, rhs);
+        let (ia, ib) = (a as i64, b as i64);
+        Ok(match op.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" | "%" if b == 0.0 => return Err(self.error(&op_token, "division by zero")),
+            "/" => a / b,
+            "%" => a % b,
+            "&" => (ia & ib) as f64,
+            "|" => (ia | ib) as f64,
+            "^" => (ia ^ ib) as f64,
+            "<<" => ia.checked_shl(ib as u32).unwrap_or(0) as f64,
+            ">>" => ia.checked_shr(ib as u32).unwrap_or(0) as f64,
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => (a < b) as u8 as f64,
+            "<=" => (a <= b) as u8 as f64,
+            ">" => (a > b) as u8 as f64,
+            ">=" => (a >= b) as u8 as f64,
+            "==" => (a == b) as u8 as f64,
+            _ => (a != b) as u8 as f64,
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next()?;
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.calc()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => (self.calc_term()? == 0.0) as u8 as f64,
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "tan" => self.calc_term()?.tan(),
+            "exp" => self.calc_term()?.exp(),
+            "log" => self.calc_term()?.ln(),
+            "sign" => self.calc_term()?.signum(),
+            "ceil" => self.calc_term()?.ceil(),
+            "floor" => self.calc_term()?.floor(),
+            // byte already assembled at an address
+            "@" => {
+                let address = self.calc_term()? as usize;
+                address
+                    .checked_sub(PROGRAM_START)
+                    .and_then(|offset| self.rom.get(offset))
+                    .copied()
+                    .unwrap_or(0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => self.constant(&token)?,
+        };
+        Ok(value)
+    }
+}
+
+fn is_binary_op(text: &str) -> bool {
+    matches!(
+        text,
+        "+" | "-"
+            | "*"
+            | "/"
+            | "%"
+            | "&"
+            | "|"
+            | "^"
+            | "<<"
+            | ">>"
+            | "pow"
+            | "min"
+            | "max"
+            | "<"
+            | "<="
+            | ">"
+            | ">="
+            | "=="
+            | "!="
+    )
+}
+
+// decimal, 0x hex or 0b binary, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        assemble(source, "test.8o").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_opcode_matches_the_reference_rom() {
+        let rom = assemble(include_str!("../roms/test_opcode.8o"), "test_opcode.8o").unwrap();
+        assert_eq!(rom, include_bytes!("../roms/test_opcode.ch8"));
+    }
+
+    #[test]
+    fn main_first_needs_no_jump() {
+        assert_eq!(assemble(": main\nclear\n", "test.8o"), Ok(vec![0x00, 0xE0]));
+        assert_eq!(
+            assemble(": sub\nreturn\n: main\nsub\n", "test.8o"),
+            Ok(vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02])
+        );
+    }
+
+    #[test]
+    fn structured_control_flow() {
+        // the loop starts at 0x200, while skips its exit jump to 0x208
+        let rom = assemble(": main\nloop\nwhile v0 != 1\nv0 += 1\nagain\n", "test.8o");
+        assert_eq!(
+            rom,
+            Ok(vec![0x40, 0x01, 0x12, 0x08, 0x70, 0x01, 0x12, 0x00])
+        );
+    }
+
+    #[test]
+    fn scroll_up() {
+        assert_eq!(
+            assemble(": main\nscroll-up 3\n", "test.8o"),
+            Ok(vec![0x00, 0xD3])
+        );
+    }
+
+    #[test]
+    fn undefined_label() {
+        assert_eq!(
+            error(": main\njump nowhere\n"),
+            "test.8o:2:6: undefined name 'nowhere'"
+        );
+    }
+
+    #[test]
+    fn fixup_out_of_range() {
+        assert_eq!(
+            error(": main\njump far\n:org 0x1000\n: far\nclear\n"),
+            "test.8o:2:6: 'far' is above 0xFFF, use i := long"
+        );
+    }
+
+    #[test]
+    fn structured_jump_out_of_range() {
+        assert_eq!(
+            error(": main\nif v0 == 1 begin\n:org 0x1000\nend\n"),
+            "test.8o:4:1: 'end' jumps to 0x1000, above 0xFFF"
+        );
+        assert_eq!(
+            error(": main\n:org 0x1000\nloop again\n"),
+            "test.8o:3:6: 'again' jumps to 0x1000, above 0xFFF"
+        );
+    }
+
+    #[test]
+    fn unclosed_block() {
+        assert_eq!(
+            error(": main\nloop\nclear\n"),
+            "test.8o:2:1: 'loop' is never closed"
+        );
+    }
+}
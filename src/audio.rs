@@ -0,0 +1,216 @@
+use crate::chip_ate::ChipAte;
+use std::io::{self, Write};
+
+// Anything that can make the CHIP-8 buzzer audible (or visible). The run loop
+// calls update once per frame after the timers tick; the buzzer sounds while
+// sound_timer is non-zero.
+pub trait AudioBackend {
+    fn update(&mut self, chip: &ChipAte);
+
+    // true while a visual backend wants the frontend to flash
+    fn flashing(&self) -> bool {
+        false
+    }
+}
+
+// Silent backend that only tracks state, for tests and headless runs
+#[derive(Debug, Default)]
+pub struct NullAudio {
+    pub active: bool,
+    pub beeps: usize,
+}
+
+impl AudioBackend for NullAudio {
+    fn update(&mut self, chip: &ChipAte) {
+        let active = chip.sound_timer > 0;
+        if active && !self.active {
+            self.beeps += 1;
+        }
+        self.active = active;
+    }
+}
+
+// Rings the terminal bell once each time the buzzer starts
+#[derive(Debug, Default)]
+pub struct BellAudio {
+    active: bool,
+}
+
+impl AudioBackend for BellAudio {
+    fn update(&mut self, chip: &ChipAte) {
+        let active = chip.sound_timer > 0;
+        if active && !self.active {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07");
+            let _ = stdout.flush();
+        }
+        self.active = active;
+    }
+}
+
+// Makes no sound, the frontend flashes while the buzzer is on
+#[derive(Debug, Default)]
+pub struct FlashAudio {
+    active: bool,
+}
+
+impl AudioBackend for FlashAudio {
+    fn update(&mut self, chip: &ChipAte) {
+        self.active = chip.sound_timer > 0;
+    }
+
+    fn flashing(&self) -> bool {
+        self.active
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioKind {
+    Sdl,
+    Bell,
+    Flash,
+    Null,
+}
+
+impl AudioKind {
+    pub const NAMES: [&'static str; 4] = ["sdl", "bell", "flash", "none"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sdl" => Some(AudioKind::Sdl),
+            "bell" => Some(AudioKind::Bell),
+            "flash" => Some(AudioKind::Flash),
+            "none" | "null" => Some(AudioKind::Null),
+            _ => None,
+        }
+    }
+}
+
+impl Default for AudioKind {
+    fn default() -> Self {
+        if cfg!(feature = "sdl") {
+            AudioKind::Sdl
+        } else {
+            AudioKind::Bell
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AudioSettings {
+    pub kind: AudioKind,
+    pub tone_hz: f32,
+    pub volume: f32, // 0.0 to 1.0
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            kind: AudioKind::default(),
+            tone_hz: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+pub fn create_backend(settings: &AudioSettings) -> Result<Box<dyn AudioBackend>, String> {
+    match settings.kind {
+        #[cfg(feature = "sdl")]
+        AudioKind::Sdl => {
+            let sdl = sdl2::init()?;
+            Ok(Box::new(sdl_audio::SdlAudio::new(&sdl, settings)?))
+        }
+        #[cfg(not(feature = "sdl"))]
+        AudioKind::Sdl => Err("chipATE was built without the sdl feature".to_string()),
+        AudioKind::Bell => Ok(Box::new(BellAudio::default())),
+        AudioKind::Flash => Ok(Box::new(FlashAudio::default())),
+        AudioKind::Null => Ok(Box::new(NullAudio::default())),
+    }
+}
+
+#[cfg(feature = "sdl")]
+pub mod sdl_audio {
+    use super::{AudioBackend, AudioSettings};
+    use crate::chip_ate::ChipAte;
+    use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+    use sdl2::Sdl;
+
+    const SAMPLE_RATE: i32 = 44100;
+    const PATTERN_BITS: f32 = 128.0;
+
+    pub struct SquareWave {
+        active: bool,
+        volume: f32,
+        phase: f32,
+        phase_inc: f32,
+        // XO-CHIP plays its 1-bit pattern buffer instead of a fixed tone
+        use_pattern: bool,
+        pattern: [u8; 16],
+        pattern_pos: f32,
+        pattern_inc: f32,
+    }
+
+    impl AudioCallback for SquareWave {
+        type Channel = f32;
+
+        fn callback(&mut self, out: &mut [f32]) {
+            for sample in out.iter_mut() {
+                if !self.active {
+                    *sample = 0.0;
+                    continue;
+                }
+                let high = if self.use_pattern {
+                    let bit = self.pattern_pos as usize;
+                    self.pattern_pos = (self.pattern_pos + self.pattern_inc) % PATTERN_BITS;
+                    self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                } else {
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                    self.phase < 0.5
+                };
+                *sample = if high { self.volume } else { -self.volume };
+            }
+        }
+    }
+
+    pub struct SdlAudio {
+        device: AudioDevice<SquareWave>,
+    }
+
+    impl SdlAudio {
+        pub fn new(sdl: &Sdl, settings: &AudioSettings) -> Result<Self, String> {
+            let audio = sdl.audio()?;
+            let desired = AudioSpecDesired {
+                freq: Some(SAMPLE_RATE),
+                channels: Some(1),
+                samples: None,
+            };
+            let device = audio.open_playback(None, &desired, |spec| SquareWave {
+                active: false,
+                volume: settings.volume.clamp(0.0, 1.0),
+                phase: 0.0,
+                phase_inc: settings.tone_hz / spec.freq as f32,
+                use_pattern: false,
+                pattern: [0; 16],
+                pattern_pos: 0.0,
+                pattern_inc: 0.0,
+            })?;
+            device.resume();
+            Ok(SdlAudio { device })
+        }
+    }
+
+    impl AudioBackend for SdlAudio {
+        fn update(&mut self, chip: &ChipAte) {
+            let freq = self.device.spec().freq as f32;
+            let mut wave = self.device.lock();
+            wave.active = chip.sound_timer > 0;
+            wave.use_pattern = chip.xo_chip;
+            if chip.xo_chip {
+                // XO-CHIP playback rate is 4000 * 2^((pitch - 64) / 48) bits per second
+                let rate = 4000.0 * 2f32.powf((chip.pitch as f32 - 64.0) / 48.0);
+                wave.pattern = chip.audio_pattern;
+                wave.pattern_inc = rate / freq;
+            }
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use crate::chip_ate::ChipAte;
+use crate::expr::Expr;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// A register that can be watched for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    // v0-vf or i, case insensitive
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "i" {
+            return Some(Register::I);
+        }
+        let digit = name.strip_prefix('v')?;
+        match u8::from_str_radix(digit, 16) {
+            Ok(n) if digit.len() == 1 => Some(Register::V(n)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(n) => write!(f, "V{:X}", n),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Stops on reads and/or writes anywhere in start..=end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    // "300", "0x300-0x30f" or either followed by :r, :w or :rw (the default)
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (range, mode) = spec.split_once(':').unwrap_or((spec, "rw"));
+        let (read, write) = match mode.to_ascii_lowercase().as_str() {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" | "wr" => (true, true),
+            _ => return None,
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => (parse_address(range)?, parse_address(range)?),
+        };
+        if end < start {
+            return None;
+        }
+        Some(Watchpoint {
+            start,
+            end,
+            read,
+            write,
+        })
+    }
+
+    fn matches(&self, address: usize, access: Access) -> bool {
+        let wanted = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        wanted && (self.start as usize..=self.end as usize).contains(&address)
+    }
+}
+
+// addresses are hex, with or without a 0x prefix
+pub fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+// An execution breakpoint, either at an address or anywhere its condition
+// becomes true
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    // source of the condition, for display
+    pub condition_text: String,
+    // stop on this hit and every one after it, 1 stops every time
+    pub hit_count: u64,
+    // times the address was reached with the condition true
+    pub hits: u64,
+    // conditions without an address stop when they turn true, not while they stay true
+    was_true: bool,
+}
+
+impl Default for Breakpoint {
+    fn default() -> Self {
+        Breakpoint {
+            condition: None,
+            condition_text: String::new(),
+            hit_count: 1,
+            hits: 0,
+            was_true: false,
+        }
+    }
+}
+
+impl Breakpoint {
+    // "[address][:hits] [if condition]", e.g. "2a0", "0x2a0:5 if v3 == 0x10" or
+    // "if mem[i+2] != 0"; needs an address, a condition or both
+    pub fn parse(spec: &str) -> Result<(Option<u16>, Breakpoint), String> {
+        let spec = spec.trim();
+        let (location, condition) = if let Some(condition) = spec.strip_prefix("if ") {
+            ("", Some(condition))
+        } else {
+            match spec.split_once(" if ") {
+                Some((location, condition)) => (location.trim(), Some(condition)),
+                None => (spec, None),
+            }
+        };
+        let (address, hit_count) = match location.split_once(':') {
+            Some((address, hits)) => (address, Some(hits)),
+            None => (location, None),
+        };
+        let address = match address {
+            "" => None,
+            address => Some(
+                parse_address(address).ok_or_else(|| format!("invalid address '{}'", address))?,
+            ),
+        };
+        let hit_count = match hit_count {
+            Some(hits) => hits
+                .parse()
+                .ok()
+                .filter(|&hits| hits > 0)
+                .ok_or_else(|| format!("invalid hit count '{}'", hits))?,
+            None => 1,
+        };
+        let condition_text = condition.unwrap_or("").trim().to_string();
+        let condition = condition
+            .map(|text| Expr::parse(text).map_err(|e| format!("condition {}", e)))
+            .transpose()?;
+        if address.is_none() && condition.is_none() {
+            return Err("breakpoint needs an address or a condition".to_string());
+        }
+        Ok((
+            address,
+            Breakpoint {
+                condition,
+                condition_text,
+                hit_count,
+                ..Breakpoint::default()
+            },
+        ))
+    }
+
+    // counts a hit if the condition holds, true once the hit count is reached
+    fn hit(&mut self, chip: &ChipAte) -> bool {
+        if self.condition.as_ref().is_some_and(|c| !c.is_true(chip)) {
+            return false;
+        }
+        self.hits += 1;
+        self.hits >= self.hit_count
+    }
+}
+
+// Why a cycle stopped before or after its instruction ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    // pc reached a breakpoint, the instruction there has not run yet
+    Breakpoint,
+    // a breakpoint without an address had its condition turn true
+    Condition,
+    Watchpoint {
+        address: u16,
+        access: Access,
+        value: u8,
+    },
+    RegisterChanged {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint => write!(f, "breakpoint"),
+            BreakReason::Condition => write!(f, "condition met"),
+            BreakReason::Watchpoint {
+                address,
+                access: Access::Read,
+                value,
+            } => write!(f, "read {:#04X} from {:#05X}", value, address),
+            BreakReason::Watchpoint {
+                address,
+                access: Access::Write,
+                value,
+            } => write!(f, "wrote {:#04X} to {:#05X}", value, address),
+            BreakReason::RegisterChanged { register, old, new } => {
+                write!(f, "{} changed {:#X} -> {:#X}", register, old, new)
+            }
+        }
+    }
+}
+
+// Everything the machine checks while running. ChipAte::cycle reports hits
+// as CycleStatus::Break; with nothing set the checks cost a few empty tests.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    addresses: BTreeMap<u16, Breakpoint>,
+    // breakpoints without an address, checked every cycle
+    conditions: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    registers: Vec<Register>,
+    // first watchpoint hit by the running instruction
+    pub(crate) hit: Option<BreakReason>,
+    // lets the next cycle run the instruction under a breakpoint that already fired
+    resuming: bool,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, address: u16) {
+        self.insert(Some(address), Breakpoint::default());
+    }
+
+    // an address breakpoint replaces any other at that address
+    pub fn insert(&mut self, address: Option<u16>, breakpoint: Breakpoint) {
+        match address {
+            Some(address) => {
+                self.addresses.insert(address, breakpoint);
+            }
+            None => self.conditions.push(breakpoint),
+        }
+    }
+
+    pub fn remove(&mut self, address: u16) {
+        self.addresses.remove(&address);
+    }
+
+    // returns whether address has a breakpoint afterwards
+    pub fn toggle(&mut self, address: u16) -> bool {
+        if self.addresses.remove(&address).is_none() {
+            self.add(address);
+        }
+        self.contains(address)
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.addresses.contains_key(&address)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = (u16, &Breakpoint)> {
+        self.addresses
+            .iter()
+            .map(|(&address, breakpoint)| (address, breakpoint))
+    }
+
+    pub fn conditions(&self) -> &[Breakpoint] {
+        &self.conditions
+    }
+
+    pub fn watch(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn watch_register(&mut self, register: Register) {
+        if !self.registers.contains(&register) {
+            self.registers.push(register);
+        }
+    }
+
+    pub fn registers(&self) -> &[Register] {
+        &self.registers
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+        self.conditions.clear();
+        self.watchpoints.clear();
+        self.registers.clear();
+    }
+
+    // call before continuing from a break so the breakpoint at pc doesn't
+    // fire again straight away
+    pub fn resume(&mut self) {
+        self.resuming = true;
+    }
+
+    // clears the resume flag, returning whether it was set
+    pub(crate) fn take_resume(&mut self) -> bool {
+        std::mem::take(&mut self.resuming)
+    }
+
+    pub(crate) fn checking_execution(&self) -> bool {
+        !self.addresses.is_empty() || !self.conditions.is_empty()
+    }
+
+    // whether the instruction at pc should stop before it runs; resuming
+    // skips the breakpoints for one cycle without counting hits
+    pub(crate) fn check_execution(
+        &mut self,
+        chip: &ChipAte,
+        resuming: bool,
+    ) -> Option<BreakReason> {
+        let mut reason = None;
+        if let Some(breakpoint) = self.addresses.get_mut(&chip.pc) {
+            if !resuming && breakpoint.hit(chip) {
+                reason = Some(BreakReason::Breakpoint);
+            }
+        }
+        for breakpoint in self.conditions.iter_mut() {
+            let is_true = breakpoint
+                .condition
+                .as_ref()
+                .is_some_and(|c| c.is_true(chip));
+            let rising = is_true && !breakpoint.was_true;
+            breakpoint.was_true = is_true;
+            if rising && !resuming {
+                breakpoint.hits += 1;
+                if reason.is_none() && breakpoint.hits >= breakpoint.hit_count {
+                    reason = Some(BreakReason::Condition);
+                }
+            }
+        }
+        reason
+    }
+
+    pub(crate) fn watching_memory(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    pub(crate) fn watching_registers(&self) -> bool {
+        !self.registers.is_empty()
+    }
+
+    // the first watched register that differs between the two snapshots
+    pub(crate) fn check_registers(
+        &self,
+        (old_v, old_i): ([u8; 16], u16),
+        (v, i): ([u8; 16], u16),
+    ) -> Option<BreakReason> {
+        self.registers.iter().find_map(|&register| {
+            let (old, new) = match register {
+                Register::V(n) => (old_v[n as usize] as u16, v[n as usize] as u16),
+                Register::I => (old_i, i),
+            };
+            (old != new).then_some(BreakReason::RegisterChanged { register, old, new })
+        })
+    }
+
+    pub(crate) fn check_access(&mut self, address: usize, access: Access, value: u8) {
+        if self.hit.is_none() && self.watchpoints.iter().any(|w| w.matches(address, access)) {
+            self.hit = Some(BreakReason::Watchpoint {
+                address: address as u16,
+                access,
+                value,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_ate::CycleStatus;
+
+    // v0 := 5, then v0 += 1 forever
+    const COUNTER: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    fn chip(program: &[u8]) -> ChipAte {
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(program).unwrap();
+        chip
+    }
+
+    fn stop(pc: u16, reason: BreakReason) -> CycleStatus {
+        CycleStatus::Break { pc, reason }
+    }
+
+    #[test]
+    fn breaks_before_the_instruction_at_an_address() {
+        let mut chip = chip(&COUNTER);
+        chip.breakpoints.add(0x202);
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.cycle(), stop(0x202, BreakReason::Breakpoint));
+        assert_eq!((chip.pc, chip.v[0]), (0x202, 5));
+        // keeps stopping until resumed
+        assert_eq!(chip.cycle(), stop(0x202, BreakReason::Breakpoint));
+        chip.breakpoints.resume();
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.v[0], 6);
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.cycle(), stop(0x202, BreakReason::Breakpoint));
+
+        assert!(!chip.breakpoints.toggle(0x202));
+        for _ in 0..6 {
+            assert_eq!(chip.cycle(), CycleStatus::Normal);
+        }
+    }
+
+    #[test]
+    fn hit_counts_and_conditions() {
+        let mut chip = chip(&COUNTER);
+        let (address, breakpoint) = Breakpoint::parse("202:2 if v0 >= 0x6").unwrap();
+        chip.breakpoints.insert(address, breakpoint);
+        // v0 is 5, then the first hit with v0 at 6, then the second
+        let mut cycles = 0;
+        while chip.cycle() == CycleStatus::Normal {
+            cycles += 1;
+        }
+        assert_eq!(cycles, 5);
+        assert_eq!(chip.v[0], 7);
+        let (_, breakpoint) = chip.breakpoints.addresses().next().unwrap();
+        assert_eq!(breakpoint.hits, 2);
+    }
+
+    #[test]
+    fn conditions_stop_when_they_turn_true() {
+        let mut chip = chip(&COUNTER);
+        let (address, breakpoint) = Breakpoint::parse("if v0 == 0x6").unwrap();
+        chip.breakpoints.insert(address, breakpoint);
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        // checked before the jump, after v0 became 6
+        assert_eq!(chip.cycle(), stop(0x204, BreakReason::Condition));
+        chip.breakpoints.resume();
+        // still true, but it didn't just turn true
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+        assert_eq!(chip.v[0], 7);
+    }
+
+    #[test]
+    fn memory_watchpoints() {
+        // i := 0x300, v0 := 0x42, save v0, i := 0x300, load v0
+        let program = [0xA3, 0x00, 0x60, 0x42, 0xF0, 0x55, 0xA3, 0x00, 0xF0, 0x65];
+        let mut chip = chip(&program);
+        chip.breakpoints
+            .watch(Watchpoint::parse("0x300:r").unwrap());
+        for _ in 0..4 {
+            assert_eq!(chip.cycle(), CycleStatus::Normal);
+        }
+        // the access has happened by the time it stops
+        assert_eq!(
+            chip.cycle(),
+            stop(
+                0x208,
+                BreakReason::Watchpoint {
+                    address: 0x300,
+                    access: Access::Read,
+                    value: 0x42
+                }
+            )
+        );
+        assert_eq!(chip.pc, 0x20A);
+
+        let mut chip = self::chip(&program);
+        chip.breakpoints
+            .watch(Watchpoint::parse("2ff-301:w").unwrap());
+        chip.cycle();
+        chip.cycle();
+        assert_eq!(
+            chip.cycle(),
+            stop(
+                0x204,
+                BreakReason::Watchpoint {
+                    address: 0x300,
+                    access: Access::Write,
+                    value: 0x42
+                }
+            )
+        );
+        assert_eq!(chip.memory[0x300], 0x42);
+    }
+
+    #[test]
+    fn register_watches() {
+        let mut chip = chip(&COUNTER);
+        chip.breakpoints
+            .watch_register(Register::from_name("V0").unwrap());
+        assert_eq!(
+            chip.cycle(),
+            stop(
+                0x200,
+                BreakReason::RegisterChanged {
+                    register: Register::V(0),
+                    old: 0,
+                    new: 5
+                }
+            )
+        );
+        // the jump leaves v0 alone
+        chip.cycle();
+        assert_eq!(chip.cycle(), CycleStatus::Normal);
+
+        let mut chip = self::chip(&[0xA3, 0x00]);
+        chip.breakpoints.watch_register(Register::I);
+        assert_eq!(
+            chip.cycle(),
+            stop(
+                0x200,
+                BreakReason::RegisterChanged {
+                    register: Register::I,
+                    old: 0,
+                    new: 0x300
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert_eq!(Watchpoint::parse("301-300"), None);
+        assert_eq!(Watchpoint::parse("300:x"), None);
+        assert_eq!(Register::from_name("v10"), None);
+        assert!(Breakpoint::parse("").is_err());
+        assert!(Breakpoint::parse("202:0").is_err());
+        assert!(Breakpoint::parse("zz").is_err());
+        assert!(Breakpoint::parse("202 if v0 ==").is_err());
+    }
+}
@@ -1,38 +1,67 @@
+use crate::breakpoints::{Access, BreakReason, Breakpoints};
+use crate::fault::EmulatorError;
 use crate::opcodes::Instruction;
-use log::debug;
-use rand::Rng;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use crate::quirks::Quirks;
+use crate::rng::ChipRng;
+use log::trace;
 
-const MEMORY_SIZE: usize = 4096;
+pub(crate) const MEMORY_SIZE: usize = 4096;
+pub(crate) const XO_MEMORY_SIZE: usize = 65536;
 const PROGRAM_START: u16 = 0x200;
 const FONT_START: u16 = 0x50;
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
+const BIG_FONT_START: u16 = 0xA0;
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 const STACK_SIZE: usize = 16;
 const REGISTER_COUNT: usize = 16;
+const RPL_FLAG_COUNT: usize = 16;
+const SCHIP_RPL_FLAG_COUNT: usize = 8;
+const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+// a 500 Hz square wave at the default pitch, so the buzzer sounds before a
+// ROM loads its own pattern with F002
+const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_SIZE] = [0xF0; AUDIO_PATTERN_SIZE];
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChipAte {
-    pub memory: [u8; MEMORY_SIZE],
+    // 4 KiB for CHIP-8/SUPER-CHIP, 64 KiB in XO-CHIP mode
+    pub memory: Vec<u8>,
     pub v: [u8; REGISTER_COUNT],
     pub i: u16,
     pub pc: u16,
     pub stack: [u16; STACK_SIZE],
     pub sp: u8,
-    pub display: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    // sized for hires, in lores only the first 64 * 32 cells are used
+    // each cell is a bitmask of the planes lit there (bit 0 plane 1, bit 1 plane 2)
+    pub display: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    pub hires: bool,
+    pub xo_chip: bool,
+    pub plane: u8,
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+    pub rpl: [u8; RPL_FLAG_COUNT],
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub keypad: [u8; REGISTER_COUNT],
     pub pressed_key: Option<u8>,
-    pub beep_active: Arc<Mutex<bool>>,
+    pub quirks: Quirks,
+    pub rng: ChipRng,
+    // identifies the loaded ROM, save states refuse to load into another one
+    pub rom_hash: u64,
+    pub breakpoints: Breakpoints,
 }
 #[derive(Debug, PartialEq, Eq)]
 pub enum CycleStatus {
     Normal,
     WaitingForKey,
+    WaitingForVblank,
+    Exited,
+    Fault { pc: u16, error: EmulatorError },
+    // a breakpoint stops before the instruction at pc, watches right after it
+    Break { pc: u16, reason: BreakReason },
 }
 
 impl Default for ChipAte {
@@ -44,33 +73,28 @@ impl Default for ChipAte {
 #[allow(dead_code)]
 impl ChipAte {
     pub fn new() -> Self {
-        let beep_active = Arc::new(Mutex::new(false));
-        let beep_thread = beep_active.clone();
-        
-        thread::spawn(move || {
-            loop {
-                thread::sleep(Duration::from_millis(50));
-                if let Ok(active) = beep_thread.lock() {
-                    if *active {
-                        print!("\x07");
-                    }
-                }
-            }
-        });
-        
         let mut chip_ate = ChipAte {
-            memory: [0; MEMORY_SIZE],
+            memory: vec![0; MEMORY_SIZE],
             stack: [0; STACK_SIZE],
             v: [0; REGISTER_COUNT],
             i: 0,
             pc: PROGRAM_START,
             sp: 0,
-            display: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            display: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            hires: false,
+            xo_chip: false,
+            plane: 1,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            rpl: [0; RPL_FLAG_COUNT],
             delay_timer: 0,
             sound_timer: 0,
             keypad: [0; REGISTER_COUNT],
             pressed_key: None,
-            beep_active,
+            quirks: Quirks::default(),
+            rng: ChipRng::default(),
+            rom_hash: 0,
+            breakpoints: Breakpoints::default(),
         };
         const FONTSET: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -90,43 +114,173 @@ impl ChipAte {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
+        // SUPER-CHIP 8x10 digits, A-F are the ones Octo uses
+        const BIG_FONTSET: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
         for (i, &byte) in FONTSET.iter().enumerate() {
             chip_ate.memory[FONT_START as usize + i] = byte;
         }
+        for (i, &byte) in BIG_FONTSET.iter().enumerate() {
+            chip_ate.memory[BIG_FONT_START as usize + i] = byte;
+        }
         chip_ate
     }
 
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    // the part of the framebuffer in use for the current resolution, row major
+    pub fn frame(&self) -> &[u8] {
+        &self.display[..self.width() * self.height()]
+    }
+
+    // XO-CHIP mode: 64 KiB address space, long I loads, drawing planes and audio patterns
+    pub fn enable_xo_chip(&mut self) {
+        self.xo_chip = true;
+        self.memory.resize(XO_MEMORY_SIZE, 0);
+    }
+
     pub fn load_rom(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let rom = std::fs::read(path)?;
-        if rom.len() > MEMORY_SIZE - PROGRAM_START as usize {
-            return Err(format!("ROM too large: {} bytes, max {} bytes", rom.len(), MEMORY_SIZE - PROGRAM_START as usize).into());
+        self.load_rom_bytes(&rom)
+    }
+
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let max_size = self.memory.len() - PROGRAM_START as usize;
+        if rom.len() > max_size {
+            return Err(
+                format!("ROM too large: {} bytes, max {} bytes", rom.len(), max_size).into(),
+            );
         }
         for (i, &byte) in rom.iter().enumerate() {
             self.memory[PROGRAM_START as usize + i] = byte;
         }
+        self.rom_hash = rom_hash(rom);
         Ok(())
     }
 
-    fn fetch(&mut self) -> u16 {
-        if self.pc as usize + 1 >= MEMORY_SIZE {
-            return 0;
+    fn fetch(&mut self) -> Result<u16, EmulatorError> {
+        if self.pc as usize + 1 >= self.memory.len() {
+            return Err(EmulatorError::PcOutOfRange { pc: self.pc });
         }
         let high = self.memory[self.pc as usize] as u16;
         let low = self.memory[(self.pc + 1) as usize] as u16;
-        self.pc += 2;
-        (high << 8) | low
+        self.pc = self.pc.wrapping_add(2);
+        Ok((high << 8) | low)
+    }
+
+    // the opcode at address without executing anything, for debuggers and disassembly
+    pub fn peek_opcode(&self, address: u16) -> Option<u16> {
+        let high = *self.memory.get(address as usize)?;
+        let low = *self.memory.get(address as usize + 1)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    // every data access goes through read and write so watchpoints see them
+    fn read(&mut self, address: usize) -> Result<u8, EmulatorError> {
+        let value = self
+            .memory
+            .get(address)
+            .copied()
+            .ok_or(EmulatorError::OutOfBounds { address })?;
+        if self.breakpoints.watching_memory() {
+            self.breakpoints.check_access(address, Access::Read, value);
+        }
+        Ok(value)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmulatorError> {
+        let byte = self
+            .memory
+            .get_mut(address)
+            .ok_or(EmulatorError::OutOfBounds { address })?;
+        *byte = value;
+        if self.breakpoints.watching_memory() {
+            self.breakpoints.check_access(address, Access::Write, value);
+        }
+        Ok(())
+    }
+
+    // skips the next instruction, in XO-CHIP that includes both words of F000 nnnn
+    fn skip(&mut self) {
+        let pc = self.pc as usize;
+        if self.xo_chip
+            && pc + 1 < self.memory.len()
+            && self.memory[pc] == 0xF0
+            && self.memory[pc + 1] == 0x00
+        {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
     }
 
-    fn execute(&mut self, instruction: Instruction) {
-        debug!("Inside of execute instruction: {:?}", instruction);
+    fn execute(&mut self, instruction: Instruction) -> Result<(), EmulatorError> {
+        // below the log level tui.log is written at, --trace records executed instructions
+        trace!("Inside of execute instruction: {:?}", instruction);
         match instruction {
             Instruction::ClearScreen => {
-                // Set all display pixels to 0 (black)
-                self.display = [0; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+                // Set the selected planes of every pixel to 0 (black)
+                for pixel in self.display.iter_mut() {
+                    *pixel &= !self.plane;
+                }
+            }
+            Instruction::ScrollDown { n } => {
+                self.scroll(0, n as isize);
+            }
+            Instruction::ScrollUp { n } => {
+                self.scroll(0, -(n as isize));
+            }
+            Instruction::ScrollRight => {
+                self.scroll(4, 0);
+            }
+            Instruction::ScrollLeft => {
+                self.scroll(-4, 0);
+            }
+            Instruction::Exit => {
+                // handled in cycle, which reports it to the run loop
+            }
+            Instruction::LowRes => {
+                // switching resolution also clears the screen
+                self.hires = false;
+                self.display = [0; HIRES_WIDTH * HIRES_HEIGHT];
+            }
+            Instruction::HighRes => {
+                self.hires = true;
+                self.display = [0; HIRES_WIDTH * HIRES_HEIGHT];
             }
             Instruction::Return => {
                 // Pop the return address from the stack and set PC to it
-                let ret = self.pop();
+                let ret = self.pop()?;
                 self.pc = ret;
             }
             Instruction::Jump { address } => {
@@ -135,25 +289,35 @@ impl ChipAte {
             }
             Instruction::Call { address } => {
                 // Push current PC to stack, then jump to subroutine address
-                self.stack[self.sp as usize] = self.pc;
-                self.sp += 1;
+                self.push(self.pc)?;
                 self.pc = address;
             }
             Instruction::SkipEq { vx, byte } => {
                 // Compare Vx to byte; skip next instruction (2 bytes) if equal
                 if self.v[vx as usize] == byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::SkipNe { vx, byte } => {
                 if self.v[vx as usize] != byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::SkipEqReg { vx, vy } => {
                 // Skip if Vx equals Vy
                 if self.v[vx as usize] == self.v[vy as usize] {
-                    self.pc += 2;
+                    self.skip();
+                }
+            }
+            Instruction::SaveRange { vx, vy } => {
+                // Store Vx through Vy (in either order) starting at I
+                for (offset, reg) in Self::register_range(vx, vy).enumerate() {
+                    self.write(self.i as usize + offset, self.v[reg])?;
+                }
+            }
+            Instruction::LoadRange { vx, vy } => {
+                for (offset, reg) in Self::register_range(vx, vy).enumerate() {
+                    self.v[reg] = self.read(self.i as usize + offset)?;
                 }
             }
             Instruction::LoadByte { vx, byte } => {
@@ -171,14 +335,23 @@ impl ChipAte {
             Instruction::Or { vx, vy } => {
                 // Bitwise OR between Vx and Vy, result in Vx
                 self.v[vx as usize] |= self.v[vy as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::And { vx, vy } => {
                 // Bitwise AND between Vx and Vy, result in Vx
                 self.v[vx as usize] &= self.v[vy as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::Xor { vx, vy } => {
                 // Bitwise XOR between Vx and Vy, result in Vx
                 self.v[vx as usize] ^= self.v[vy as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::AddReg { vx, vy } => {
                 // Add Vy to Vx, set VF to 1 if carry occurs (overflow)
@@ -192,9 +365,16 @@ impl ChipAte {
                 self.v[vx as usize] = result;
                 self.v[0xF] = (!borrow) as u8;
             }
-            Instruction::Shr { vx, vy: _ } => {
-                self.v[0xF] = self.v[vx as usize] & 0x1;
-                self.v[vx as usize] >>= 1;
+            Instruction::Shr { vx, vy } => {
+                // Shift right by 1, VF gets the bit shifted out
+                // the VIP shifted Vy into Vx, later interpreters shift Vx in place
+                let value = if self.quirks.shift_uses_vy {
+                    self.v[vy as usize]
+                } else {
+                    self.v[vx as usize]
+                };
+                self.v[vx as usize] = value >> 1;
+                self.v[0xF] = value & 0x1;
             }
             Instruction::SubN { vx, vy } => {
                 // Set Vx to Vy - Vx, VF is 1 if no borrow (Vy >= Vx)
@@ -202,15 +382,20 @@ impl ChipAte {
                 self.v[vx as usize] = result;
                 self.v[0xF] = (!borrow) as u8;
             }
-            Instruction::Shl { vx } => {
+            Instruction::Shl { vx, vy } => {
                 // Shift Vx left by 1, VF gets the bit shifted out
-                self.v[0xF] = (self.v[vx as usize] >> 7) & 0x1;
-                self.v[vx as usize] <<= 1;
+                let value = if self.quirks.shift_uses_vy {
+                    self.v[vy as usize]
+                } else {
+                    self.v[vx as usize]
+                };
+                self.v[vx as usize] = value << 1;
+                self.v[0xF] = (value >> 7) & 0x1;
             }
             Instruction::SkipNeReg { vx, vy } => {
                 // Skip if Vx does not equal Vy
                 if self.v[vx as usize] != self.v[vy as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             Instruction::LoadI { address } => {
@@ -219,46 +404,95 @@ impl ChipAte {
             }
             Instruction::JumpV0 { address } => {
                 // Jump to address plus V0, useful for computed jumps
-                self.pc = address + self.v[0] as u16;
+                // CHIP-48 and SUPER-CHIP read this as Bxnn and add Vx instead
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v[((address >> 8) & 0xF) as usize]
+                } else {
+                    self.v[0]
+                };
+                self.pc = address + offset as u16;
             }
             Instruction::Random { vx, byte } => {
                 // Generate a random byte, AND it with byte, store in Vx
-                let mut rng = rand::rng();
-                let random_byte: u8 = rng.random();
+                let random_byte = self.rng.next_byte();
                 self.v[vx as usize] = random_byte & byte;
             }
             Instruction::Draw { vx, vy, n } => {
-                let x = self.v[vx as usize] as usize % DISPLAY_WIDTH;
-                let y = self.v[vy as usize] as usize % DISPLAY_HEIGHT;
+                let (width, height) = (self.width(), self.height());
+                let x = self.v[vx as usize] as usize % width;
+                let y = self.v[vy as usize] as usize % height;
+                // Dxy0 is a 16x16 sprite stored as two bytes per row
+                let (sprite_width, rows) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let bytes_per_row = sprite_width / 8;
                 self.v[0xF] = 0;
-                for row in 0..n as usize {
-                    let sprite = self.memory[(self.i + row as u16) as usize];
-                    for col in 0..8 {
-                        if (sprite & (0x80 >> col)) != 0 {
-                            let idx = (y + row) * DISPLAY_WIDTH + (x + col);
-                            if idx < DISPLAY_WIDTH * DISPLAY_HEIGHT {
-                                let pixel = &mut self.display[idx];
-                                if *pixel == 1 {
+                // with both planes selected the sprite data for plane 2
+                // directly follows the data for plane 1
+                let mut sprite_start = self.i as usize;
+                for plane in [1, 2] {
+                    if self.plane & plane == 0 {
+                        continue;
+                    }
+                    // the starting position always wraps, the sprite itself either
+                    // clips at the edge or wraps around depending on the quirk
+                    for row in 0..rows {
+                        if self.quirks.clip_sprites && y + row >= height {
+                            break;
+                        }
+                        let py = (y + row) % height;
+                        let addr = sprite_start + row * bytes_per_row;
+                        let sprite = if bytes_per_row == 2 {
+                            (self.read(addr)? as u16) << 8 | self.read(addr + 1)? as u16
+                        } else {
+                            (self.read(addr)? as u16) << 8
+                        };
+                        for col in 0..sprite_width {
+                            if self.quirks.clip_sprites && x + col >= width {
+                                break;
+                            }
+                            let px = (x + col) % width;
+                            if (sprite & (0x8000 >> col)) != 0 {
+                                let pixel = &mut self.display[py * width + px];
+                                if *pixel & plane != 0 {
                                     self.v[0xF] = 1;
                                 }
-                                *pixel ^= 1;
+                                *pixel ^= plane;
                             }
                         }
                     }
+                    sprite_start += rows * bytes_per_row;
                 }
             }
             Instruction::SkipKey { vx } => {
                 // skip if the key indexed by Vx is pressed
-                if self.keypad[self.v[vx as usize] as usize] == 1 {
-                    self.pc += 2;
+                if self.keypad[(self.v[vx as usize] & 0xF) as usize] == 1 {
+                    self.skip();
                 }
             }
             Instruction::SkipNoKey { vx } => {
                 // skip if the key indexed by Vx is not pressed
-                if self.keypad[self.v[vx as usize] as usize] == 0 {
-                    self.pc += 2;
+                if self.keypad[(self.v[vx as usize] & 0xF) as usize] == 0 {
+                    self.skip();
+                }
+            }
+            Instruction::LoadILong => {
+                // the address is the word following the instruction
+                let high = self.read(self.pc as usize)? as u16;
+                let low = self.read(self.pc as usize + 1)? as u16;
+                self.i = (high << 8) | low;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            Instruction::SelectPlane { n } => {
+                self.plane = n & 0x3;
+            }
+            Instruction::LoadAudio => {
+                let start = self.i as usize;
+                for offset in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[offset] = self.read(start + offset)?;
                 }
             }
+            Instruction::SetPitch { vx } => {
+                self.pitch = self.v[vx as usize];
+            }
             Instruction::LoadDelay { vx } => {
                 // load current delay timer value into Vx
                 self.v[vx as usize] = self.delay_timer;
@@ -268,7 +502,7 @@ impl ChipAte {
                     self.v[vx as usize] = key;
                     self.pressed_key = None;
                 } else {
-                    self.pc -= 2;
+                    self.pc = self.pc.wrapping_sub(2);
                 }
             }
             Instruction::SetDelay { vx } => {
@@ -286,51 +520,112 @@ impl ChipAte {
             Instruction::LoadFont { vx } => {
                 // set I to the memory address of the font sprite for digit Vx
                 // each sprite is 5 bytes, so Vx * 5 gives the offset from 0x000
-                self.i = FONT_START + (self.v[vx as usize] * 5) as u16;
+                self.i = FONT_START + (self.v[vx as usize] & 0xF) as u16 * 5;
+            }
+            Instruction::LoadBigFont { vx } => {
+                // big font sprites are 10 bytes each
+                self.i = BIG_FONT_START + (self.v[vx as usize] & 0xF) as u16 * 10;
             }
             Instruction::StoreBCD { vx } => {
                 // Convert Vx to binary-coded decimal and store at I, I+1, I+2
                 let value = self.v[vx as usize];
-                self.memory[self.i as usize] = value / 100; // Hundreds
-                self.memory[self.i as usize + 1] = (value / 10) % 10; // Tens
-                self.memory[self.i as usize + 2] = value % 10; // Ones
+                self.write(self.i as usize, value / 100)?; // Hundreds
+                self.write(self.i as usize + 1, (value / 10) % 10)?; // Tens
+                self.write(self.i as usize + 2, value % 10)?; // Ones
             }
             Instruction::StoreRegs { vx } => {
                 // Store registers V0 through Vx into memory starting at I
                 for reg in 0..=vx as usize {
-                    self.memory[self.i as usize + reg] = self.v[reg];
+                    self.write(self.i as usize + reg, self.v[reg])?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(vx as u16 + 1);
                 }
             }
             Instruction::LoadRegs { vx } => {
                 // Load registers V0 through Vx from memory starting at I
                 for reg in 0..=vx as usize {
-                    self.v[reg] = self.memory[self.i as usize + reg];
+                    self.v[reg] = self.read(self.i as usize + reg)?;
                 }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(vx as u16 + 1);
+                }
+            }
+            Instruction::StoreFlags { vx } => {
+                // the HP-48 only had 8 RPL flags, XO-CHIP allows all 16
+                let count = (vx as usize + 1).min(self.rpl_flag_count());
+                self.rpl[..count].copy_from_slice(&self.v[..count]);
+            }
+            Instruction::LoadFlags { vx } => {
+                let count = (vx as usize + 1).min(self.rpl_flag_count());
+                self.v[..count].copy_from_slice(&self.rpl[..count]);
             }
             Instruction::Unknown { opcode } => {
-                // log unrecognized opcodes for debugging
-                println!("Unknown opcode: {:#X}", opcode);
+                // the run loop decides what to do with it based on the fault policy
+                return Err(EmulatorError::UnknownOpcode { opcode });
             }
         }
+        Ok(())
     }
     pub fn cycle(&mut self) -> CycleStatus {
-        let opcode = self.fetch();
+        let pc = self.pc;
+        let resuming = self.breakpoints.take_resume();
+        if self.breakpoints.checking_execution() {
+            // the conditions need the whole machine, so check them on a moved-out copy
+            let mut breakpoints = std::mem::take(&mut self.breakpoints);
+            let reason = breakpoints.check_execution(self, resuming);
+            self.breakpoints = breakpoints;
+            if let Some(reason) = reason {
+                return CycleStatus::Break { pc, reason };
+            }
+        }
+        let registers = self
+            .breakpoints
+            .watching_registers()
+            .then_some((self.v, self.i));
+
+        let opcode = match self.fetch() {
+            Ok(opcode) => opcode,
+            Err(error) => return CycleStatus::Fault { pc, error },
+        };
         let instruction = Instruction::from_opcode(opcode);
-        match instruction {
+        let result = match instruction {
             Instruction::WaitKey { vx } => {
                 if let Some(key) = self.pressed_key {
                     self.v[vx as usize] = key;
                     self.pressed_key = None;
-                    CycleStatus::Normal
+                    Ok(CycleStatus::Normal)
                 } else {
-                    self.pc -= 2;
-                    CycleStatus::WaitingForKey
+                    self.pc = self.pc.wrapping_sub(2);
+                    Ok(CycleStatus::WaitingForKey)
                 }
             }
-            _ => {
-                self.execute(instruction);
-                CycleStatus::Normal
+            Instruction::Exit => {
+                // stay on 00FD so any further cycles keep reporting the exit
+                self.pc = self.pc.wrapping_sub(2);
+                Ok(CycleStatus::Exited)
+            }
+            Instruction::Draw { .. } if self.quirks.display_wait => {
+                // the VIP only drew during vblank, so nothing else runs this frame
+                self.execute(instruction)
+                    .map(|_| CycleStatus::WaitingForVblank)
             }
+            _ => self.execute(instruction).map(|_| CycleStatus::Normal),
+        };
+        let watch_hit = self.breakpoints.hit.take();
+        let status = match result {
+            Ok(status) => status,
+            Err(error) => return CycleStatus::Fault { pc, error },
+        };
+        if status == CycleStatus::WaitingForKey && resuming {
+            // still on the same instruction, keep ignoring its breakpoint
+            self.breakpoints.resume();
+        }
+        let register_hit =
+            registers.and_then(|old| self.breakpoints.check_registers(old, (self.v, self.i)));
+        match watch_hit.or(register_hit) {
+            Some(reason) => CycleStatus::Break { pc, reason },
+            None => status,
         }
     }
 
@@ -342,29 +637,82 @@ impl ChipAte {
         self.delay_timer
     }
 
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        self.keypad[key as usize] = pressed as u8;
+        if pressed {
+            self.pressed_key = Some(key);
+        }
+    }
+
+    // restarts the Cxkk sequence so the run is reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = ChipRng::new(seed);
+    }
+
     pub fn set_sound_timer(&mut self, new_time: u8) {
         self.sound_timer = new_time
     }
-    pub fn push(&mut self, val: u16) {
+    pub fn push(&mut self, val: u16) -> Result<(), EmulatorError> {
         if (self.sp as usize) < STACK_SIZE {
             self.stack[self.sp as usize] = val;
             self.sp += 1;
+            Ok(())
+        } else {
+            Err(EmulatorError::StackOverflow)
         }
     }
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, EmulatorError> {
         if self.sp > 0 {
             self.sp -= 1;
-            self.stack[self.sp as usize]
+            Ok(self.stack[self.sp as usize])
         } else {
-            0
+            Err(EmulatorError::StackUnderflow)
+        }
+    }
+
+    // shifts the selected planes of the visible display by dx, dy pixels,
+    // filling the gap with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.display;
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let in_bounds = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                let moved = if in_bounds {
+                    old[(src_y * width + src_x) as usize] & self.plane
+                } else {
+                    0
+                };
+                let pixel = &mut self.display[(y * width + x) as usize];
+                *pixel = (*pixel & !self.plane) | moved;
+            }
+        }
+    }
+
+    fn register_range(vx: u8, vy: u8) -> Box<dyn Iterator<Item = usize>> {
+        if vx <= vy {
+            Box::new(vx as usize..=vy as usize)
+        } else {
+            Box::new((vy as usize..=vx as usize).rev())
+        }
+    }
+
+    fn rpl_flag_count(&self) -> usize {
+        if self.xo_chip {
+            RPL_FLAG_COUNT
+        } else {
+            SCHIP_RPL_FLAG_COUNT
         }
     }
 
     pub fn render_display(&self) -> String {
-        let mut output = String::with_capacity(DISPLAY_WIDTH * DISPLAY_HEIGHT + DISPLAY_HEIGHT);
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                output.push(if self.display[y * DISPLAY_WIDTH + x] == 1 {
+        let (width, height) = (self.width(), self.height());
+        let mut output = String::with_capacity(width * height + height);
+        for y in 0..height {
+            for x in 0..width {
+                output.push(if self.display[y * width + x] != 0 {
                     '█'
                 } else {
                     ' '
@@ -378,13 +726,321 @@ impl ChipAte {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
+        // the buzzer itself is driven from sound_timer by an AudioBackend
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
-            if let Ok(mut active) = self.beep_active.lock() {
-                *active = true;
+        }
+    }
+}
+
+// 64-bit FNV-1a, stable across builds unlike std's DefaultHasher
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(program: &[u8]) -> ChipAte {
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(program).unwrap();
+        chip
+    }
+
+    fn xo_chip(program: &[u8]) -> ChipAte {
+        let mut chip = ChipAte::new();
+        chip.enable_xo_chip();
+        chip.load_rom_bytes(program).unwrap();
+        chip
+    }
+
+    fn run(chip: &mut ChipAte, cycles: usize) {
+        for _ in 0..cycles {
+            assert_eq!(chip.cycle(), CycleStatus::Normal, "pc {:#X}", chip.pc);
+        }
+    }
+
+    #[test]
+    fn long_load_and_skipping_over_it() {
+        // i := long 0x1234, then skip an i := long 0x5678 since v0 == 0
+        let program = [0xF0, 0x00, 0x12, 0x34, 0x30, 0x00, 0xF0, 0x00, 0x56, 0x78];
+        let mut chip = xo_chip(&program);
+        run(&mut chip, 1);
+        assert_eq!((chip.i, chip.pc), (0x1234, 0x204));
+        run(&mut chip, 1);
+        assert_eq!(chip.pc, 0x20A);
+
+        // outside XO-CHIP a skip is always one word
+        let mut chip = self::chip(&program);
+        run(&mut chip, 2);
+        assert_eq!(chip.pc, 0x208);
+    }
+
+    #[test]
+    fn register_ranges_leave_i_alone() {
+        // save v1 - v3, save v3 - v1, load v4 - v6
+        let mut chip = xo_chip(&[0x51, 0x32, 0x53, 0x12, 0x54, 0x63]);
+        chip.v[1..4].copy_from_slice(&[1, 2, 3]);
+        chip.i = 0x300;
+        run(&mut chip, 1);
+        assert_eq!(&chip.memory[0x300..0x304], &[1, 2, 3, 0]);
+        run(&mut chip, 1);
+        assert_eq!(&chip.memory[0x300..0x304], &[3, 2, 1, 0]);
+        run(&mut chip, 1);
+        assert_eq!(&chip.v[4..7], &[3, 2, 1]);
+        assert_eq!(chip.i, 0x300);
+    }
+
+    #[test]
+    fn plane_selection() {
+        let mut chip = xo_chip(&[0xF2, 0x01, 0xF3, 0x01, 0xF0, 0x01]);
+        assert_eq!(chip.plane, 1);
+        run(&mut chip, 1);
+        assert_eq!(chip.plane, 2);
+        run(&mut chip, 1);
+        assert_eq!(chip.plane, 3);
+        run(&mut chip, 1);
+        assert_eq!(chip.plane, 0);
+    }
+
+    #[test]
+    fn drawing_on_two_planes() {
+        // plane 3, draw, plane 2, draw, plane 1, clear
+        let program = [
+            0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0xD0, 0x01, 0xF1, 0x01, 0x00, 0xE0,
+        ];
+        let mut chip = xo_chip(&program);
+        chip.i = 0x300;
+        // plane 1's row, then plane 2's
+        chip.memory[0x300] = 0xC0;
+        chip.memory[0x301] = 0x60;
+        run(&mut chip, 2);
+        assert_eq!(&chip.display[..3], &[1, 3, 2]);
+        assert_eq!(chip.v[0xF], 0);
+
+        // plane 2 alone reads the first row and only collides on its own plane
+        run(&mut chip, 2);
+        assert_eq!(&chip.display[..3], &[3, 1, 2]);
+        assert_eq!(chip.v[0xF], 1);
+
+        // clearing plane 1 leaves plane 2 lit
+        run(&mut chip, 2);
+        assert_eq!(&chip.display[..3], &[2, 0, 2]);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // i := 0x300, audio, v0 := 100, pitch := v0
+        let mut chip = xo_chip(&[0xA3, 0x00, 0xF0, 0x02, 0x60, 0x64, 0xF0, 0x3A]);
+        // the buzzer has something to play before F002
+        assert_ne!(chip.audio_pattern, [0; AUDIO_PATTERN_SIZE]);
+        chip.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        run(&mut chip, 4);
+        assert_eq!(chip.audio_pattern, [0xAA; 16]);
+        assert_eq!(chip.pitch, 100);
+    }
+
+    #[test]
+    fn scrolling_in_both_resolutions() {
+        // scroll down 2, right 4, left 4, up 1
+        let program = [0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xD1];
+        for (hires, width) in [(false, LORES_WIDTH), (true, HIRES_WIDTH)] {
+            let mut chip = self::chip(&program);
+            chip.hires = hires;
+            chip.display[3] = 1;
+            run(&mut chip, 1);
+            assert_eq!(chip.display[2 * width + 3], 1);
+            run(&mut chip, 1);
+            assert_eq!(chip.display[2 * width + 7], 1);
+            run(&mut chip, 1);
+            assert_eq!(chip.display[2 * width + 3], 1);
+            run(&mut chip, 1);
+            assert_eq!(chip.display[width + 3], 1);
+            assert_eq!(chip.display.iter().filter(|&&p| p != 0).count(), 1);
+        }
+    }
+
+    #[test]
+    fn scrolling_drops_pixels_at_the_edge() {
+        let mut chip = chip(&[0x00, 0xFB]);
+        chip.display[LORES_WIDTH - 1] = 1;
+        run(&mut chip, 1);
+        assert!(chip.display.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn big_sprites_collide() {
+        // hires, draw a 16x16 sprite twice
+        let mut chip = chip(&[0x00, 0xFF, 0xD0, 0x10, 0xD0, 0x10]);
+        chip.i = 0x300;
+        chip.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        run(&mut chip, 2);
+        assert_eq!(chip.v[0xF], 0);
+        for y in 0..HIRES_HEIGHT {
+            for x in 0..HIRES_WIDTH {
+                let lit = x < 16 && y < 16;
+                assert_eq!(chip.display[y * HIRES_WIDTH + x], lit as u8, "{x},{y}");
+            }
+        }
+        run(&mut chip, 1);
+        assert_eq!(chip.v[0xF], 1);
+        assert!(chip.display.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // save v0 - vf to the flags, clear the registers, load them back
+        let program = [0xFF, 0x75, 0x00, 0xE0, 0xFF, 0x85];
+        let mut chip = chip(&program);
+        chip.v = std::array::from_fn(|n| n as u8 + 1);
+        run(&mut chip, 1);
+        chip.v = [0; REGISTER_COUNT];
+        run(&mut chip, 2);
+        // SUPER-CHIP only has 8 flags
+        assert_eq!(&chip.v[..9], &[1, 2, 3, 4, 5, 6, 7, 8, 0]);
+
+        let mut chip = xo_chip(&program);
+        let registers = std::array::from_fn(|n| n as u8 + 1);
+        chip.v = registers;
+        run(&mut chip, 1);
+        chip.v = [0; REGISTER_COUNT];
+        run(&mut chip, 2);
+        assert_eq!(chip.v, registers);
+    }
+
+    #[test]
+    fn exit_stays_exited() {
+        let mut chip = chip(&[0x00, 0xFD]);
+        assert_eq!(chip.cycle(), CycleStatus::Exited);
+        assert_eq!(chip.pc, PROGRAM_START);
+        assert_eq!(chip.cycle(), CycleStatus::Exited);
+    }
+
+    #[test]
+    fn shift_quirk() {
+        // v1 >>= v2, v3 <<= v2
+        let program = [0x81, 0x26, 0x83, 0x2E];
+        for shift_uses_vy in [false, true] {
+            let mut chip = chip(&program);
+            chip.quirks.shift_uses_vy = shift_uses_vy;
+            chip.v[1] = 0b0000_0100;
+            chip.v[2] = 0b1000_0001;
+            chip.v[3] = 0b0000_0010;
+            run(&mut chip, 1);
+            if shift_uses_vy {
+                assert_eq!((chip.v[1], chip.v[0xF]), (0b0100_0000, 1));
+            } else {
+                assert_eq!((chip.v[1], chip.v[0xF]), (0b0000_0010, 0));
+            }
+            run(&mut chip, 1);
+            if shift_uses_vy {
+                assert_eq!((chip.v[3], chip.v[0xF]), (0b0000_0010, 1));
+            } else {
+                assert_eq!((chip.v[3], chip.v[0xF]), (0b0000_0100, 0));
             }
-        } else if let Ok(mut active) = self.beep_active.lock() {
-            *active = false;
+        }
+    }
+
+    #[test]
+    fn load_store_quirk() {
+        // save v2, load v2
+        for (increments, after_store, after_load) in [(true, 0x303, 0x306), (false, 0x300, 0x300)] {
+            let mut chip = chip(&[0xF2, 0x55, 0xF2, 0x65]);
+            chip.quirks.load_store_increments_i = increments;
+            chip.i = 0x300;
+            run(&mut chip, 1);
+            assert_eq!(chip.i, after_store);
+            run(&mut chip, 1);
+            assert_eq!(chip.i, after_load);
+        }
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        // v0 |= v1, v0 &= v1, v0 ^= v1
+        let program = [0x80, 0x11, 0x80, 0x12, 0x80, 0x13];
+        for vf_reset in [false, true] {
+            let mut chip = chip(&program);
+            chip.quirks.vf_reset = vf_reset;
+            for _ in 0..3 {
+                chip.v[0xF] = 7;
+                run(&mut chip, 1);
+                assert_eq!(chip.v[0xF], if vf_reset { 0 } else { 7 });
+            }
+        }
+    }
+
+    #[test]
+    fn jump_quirk() {
+        // jump0 0x310
+        for (jump_uses_vx, target) in [(false, 0x311), (true, 0x313)] {
+            let mut chip = chip(&[0xB3, 0x10]);
+            chip.quirks.jump_uses_vx = jump_uses_vx;
+            chip.v[0] = 1;
+            chip.v[3] = 3;
+            run(&mut chip, 1);
+            assert_eq!(chip.pc, target);
+        }
+    }
+
+    #[test]
+    fn clip_quirk() {
+        // an 8x2 sprite drawn at the bottom right corner
+        for clip_sprites in [false, true] {
+            let mut chip = chip(&[0xD0, 0x12]);
+            chip.quirks.clip_sprites = clip_sprites;
+            chip.v[0] = 60;
+            chip.v[1] = 31;
+            chip.i = 0x300;
+            chip.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+            run(&mut chip, 1);
+            let lit = |x: usize, y: usize| chip.display[y * LORES_WIDTH + x] == 1;
+            assert!(lit(60, 31) && lit(63, 31));
+            assert_eq!(lit(0, 31), !clip_sprites);
+            assert_eq!(lit(60, 0), !clip_sprites);
+            assert_eq!(lit(0, 0), !clip_sprites);
+        }
+    }
+
+    #[test]
+    fn stack_faults() {
+        // a subroutine that calls itself
+        let mut chip = chip(&[0x22, 0x00]);
+        run(&mut chip, STACK_SIZE);
+        assert_eq!(
+            chip.cycle(),
+            CycleStatus::Fault {
+                pc: 0x200,
+                error: EmulatorError::StackOverflow
+            }
+        );
+
+        let mut chip = self::chip(&[0x00, 0xEE]);
+        assert_eq!(
+            chip.cycle(),
+            CycleStatus::Fault {
+                pc: 0x200,
+                error: EmulatorError::StackUnderflow
+            }
+        );
+    }
+
+    #[test]
+    fn memory_faults_past_the_end() {
+        // bcd v0, save v1
+        for program in [[0xF0, 0x33], [0xF1, 0x55]] {
+            let mut chip = chip(&program);
+            chip.i = 0xFFF;
+            assert_eq!(
+                chip.cycle(),
+                CycleStatus::Fault {
+                    pc: 0x200,
+                    error: EmulatorError::OutOfBounds { address: 0x1000 }
+                }
+            );
         }
     }
 }
@@ -0,0 +1,567 @@
+use crate::config::{Config, Settings};
+use chipate::audio::{AudioKind, AudioSettings};
+use chipate::breakpoints::{Breakpoint, Register, Watchpoint};
+use chipate::fault::FaultPolicy;
+use chipate::headless::KeyPress;
+use chipate::keymap::Keymap;
+use chipate::quirks::Quirks;
+use chipate::render::RenderMode;
+use chipate::screenshot::{self, Rgb, DEFAULT_PALETTE};
+use chipate::theme::{ColorDepth, Glyph, Scheme, Theme};
+use chipate::trace;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrontendKind {
+    #[default]
+    Tui,
+    Sdl,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub rom_path: String,
+    pub cycles_per_frame: usize,
+    pub frame_rate: u32,
+    pub quirks: Quirks,
+    pub xo_chip: bool,
+    pub fault_policy: FaultPolicy,
+    pub seed: Option<u64>,
+    pub audio: AudioSettings,
+    pub keymap: Keymap,
+    pub theme: Theme,
+    pub render_mode: RenderMode,
+    pub log_file: PathBuf,
+    pub log_level: tracing::Level,
+    pub frontend: FrontendKind,
+    // pixel size of the SDL window and PNG screenshots in lores
+    pub scale: u32,
+    pub max_frames: Option<u64>,
+    pub state_dir: PathBuf,
+    pub screenshot_dir: PathBuf,
+    // colours of screenshots and recordings
+    pub palette: [Rgb; 4],
+    // GIF recording from the first frame until quitting
+    pub record: Option<PathBuf>,
+    pub rewind_depth: usize,
+    pub rewind_interval: u64,
+    pub breakpoints: Vec<(Option<u16>, Breakpoint)>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_registers: Vec<Register>,
+    pub trace: Option<PathBuf>,
+    // pc ranges written to the trace, all of them when empty
+    pub trace_ranges: Vec<(u16, u16)>,
+    // keypad input movie written when quitting
+    pub record_movie: Option<PathBuf>,
+    // movie whose input replaces the keypad
+    pub replay: Option<PathBuf>,
+}
+
+// chipATE disasm <rom_path> [--xo-chip] [-o <file>]
+#[derive(Debug)]
+pub struct DisasmArgs {
+    pub rom_path: String,
+    pub xo_chip: bool,
+    // stdout when not given
+    pub output: Option<PathBuf>,
+}
+
+impl DisasmArgs {
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {} disasm <rom_path> [--xo-chip] [-o <file.8o>]",
+            program
+        )
+    }
+
+    // args still include the program name and the subcommand
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom_path = None;
+        let mut xo_chip = false;
+        let mut output = None;
+
+        let mut iter = args.iter().skip(2);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--xo-chip" => xo_chip = true,
+                "-o" | "--output" => {
+                    output = Some(iter.next().ok_or("-o needs a file name")?.into());
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+
+        let rom_path = rom_path.ok_or("Missing ROM path")?;
+        xo_chip |= rom_path.ends_with(".xo8");
+        Ok(DisasmArgs {
+            rom_path,
+            xo_chip,
+            output,
+        })
+    }
+}
+
+// chipATE trace-diff <left> <right>
+#[derive(Debug)]
+pub struct TraceDiffArgs {
+    pub left: PathBuf,
+    pub right: PathBuf,
+}
+
+impl TraceDiffArgs {
+    pub fn usage(program: &str) -> String {
+        format!("Usage: {} trace-diff <left.trace> <right.trace>", program)
+    }
+
+    // args still include the program name and the subcommand
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match &args[2.min(args.len())..] {
+            [left, right] => Ok(TraceDiffArgs {
+                left: left.into(),
+                right: right.into(),
+            }),
+            [_] | [] => Err("trace-diff needs two trace files".to_string()),
+            [_, _, extra, ..] => Err(format!("Unexpected argument: {}", extra)),
+        }
+    }
+}
+
+// chipATE test <rom_path> [--frames <n>] [--keys <script>] [--expect <file>] ...
+#[derive(Debug)]
+pub struct TestArgs {
+    pub rom_path: String,
+    // the movie's length when replaying, otherwise 300
+    pub frames: Option<u64>,
+    pub cycles_per_frame: usize,
+    pub quirks: Quirks,
+    pub xo_chip: bool,
+    pub seed: Option<u64>,
+    pub keys: Vec<KeyPress>,
+    // text bitmap the final screen has to match
+    pub expect: Option<PathBuf>,
+    pub expect_hash: Option<u64>,
+    // where to write the final screen, for making expectations
+    pub dump: Option<PathBuf>,
+    // png, pbm or txt, by extension
+    pub screenshot: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub scale: u32,
+    pub palette: [Rgb; 4],
+    // movie to replay, a desync fails the test
+    pub replay: Option<PathBuf>,
+}
+
+impl TestArgs {
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {} test <rom_path|source.8o> [--frames <n>] [--cycles <n>] [--quirks <{}>] [--xo-chip] \
+             [--seed <n>] [--keys <frame:key[:hold] ...>] [--expect <screen.txt>] [--expect-hash <hex>] \
+             [--dump <screen.txt>] [--screenshot <file.png|file.pbm|file.txt>] \
+             [--record <file.gif>] [--scale <n>] [--palette <rgb,rgb,rgb,rgb>] [--replay <file.movie>]",
+            program,
+            Quirks::PRESETS.join("|")
+        )
+    }
+
+    // args still include the program name and the subcommand
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom_path = None;
+        let mut frames = None;
+        let mut cycles_per_frame = 12;
+        let mut quirks = Quirks::default();
+        let mut xo_chip = false;
+        let mut seed = None;
+        let mut keys = Vec::new();
+        let mut expect = None;
+        let mut expect_hash = None;
+        let mut dump = None;
+        let mut screenshot = None;
+        let mut record = None;
+        let mut scale = 10;
+        let mut palette = DEFAULT_PALETTE;
+        let mut replay = None;
+
+        let mut iter = args.iter().skip(2);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--frames" => {
+                    let value = iter.next().ok_or("--frames needs a number")?;
+                    frames = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid frame count: {}", value))?,
+                    );
+                }
+                "--cycles" => {
+                    let value = iter.next().ok_or("--cycles needs a number")?;
+                    cycles_per_frame = value
+                        .parse()
+                        .map_err(|_| format!("Invalid cycle count: {}", value))?;
+                }
+                "--quirks" => {
+                    let name = iter.next().ok_or("--quirks needs a preset name")?;
+                    quirks = Quirks::from_name(name)
+                        .ok_or_else(|| format!("Unknown quirks preset: {}", name))?;
+                }
+                "--xo-chip" => xo_chip = true,
+                "--seed" => {
+                    let value = iter.next().ok_or("--seed needs a number")?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid seed: {}", value))?,
+                    );
+                }
+                "--keys" => {
+                    let script = iter.next().ok_or("--keys needs a key script")?;
+                    keys.extend(KeyPress::parse_script(script)?);
+                }
+                "--expect" => {
+                    expect = Some(iter.next().ok_or("--expect needs a file name")?.into());
+                }
+                "--expect-hash" => {
+                    let value = iter.next().ok_or("--expect-hash needs a hash")?;
+                    let digits = value.trim_start_matches("0x");
+                    expect_hash = Some(
+                        u64::from_str_radix(digits, 16)
+                            .map_err(|_| format!("Invalid hash: {}", value))?,
+                    );
+                }
+                "--dump" => {
+                    dump = Some(iter.next().ok_or("--dump needs a file name")?.into());
+                }
+                "--screenshot" => {
+                    screenshot = Some(iter.next().ok_or("--screenshot needs a file name")?.into());
+                }
+                "--record" => {
+                    record = Some(iter.next().ok_or("--record needs a file name")?.into());
+                }
+                "--scale" => {
+                    let value = iter.next().ok_or("--scale needs a number")?;
+                    scale = value
+                        .parse()
+                        .ok()
+                        .filter(|&scale| scale > 0)
+                        .ok_or_else(|| format!("Invalid scale: {}", value))?;
+                }
+                "--palette" => {
+                    let value = iter.next().ok_or("--palette needs four colours")?;
+                    palette = screenshot::parse_palette(value)
+                        .ok_or_else(|| format!("Invalid palette: {}", value))?;
+                }
+                "--replay" => {
+                    replay = Some(iter.next().ok_or("--replay needs a file name")?.into());
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+
+        let rom_path = rom_path.ok_or("Missing ROM path")?;
+        xo_chip |= rom_path.ends_with(".xo8");
+        Ok(TestArgs {
+            rom_path,
+            frames,
+            cycles_per_frame,
+            quirks,
+            xo_chip,
+            seed,
+            keys,
+            expect,
+            expect_hash,
+            dump,
+            screenshot,
+            record,
+            scale,
+            palette,
+            replay,
+        })
+    }
+}
+
+impl Args {
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {} [run] <rom_path|source.8o> [cycles_per_frame] [--quirks <{}>] [--xo-chip] [--on-fault <{}>] [--seed <n>] \
+             [--audio <{}>] [--tone <hz>] [--volume <0-100>] \
+             [--frontend <tui|sdl>] [--scale <n>] [--frames <n>] \
+             [--theme <{}>] [--glyph <{}>] [--colors <{}>] [--render <{}>] \
+             [--state-dir <dir>] [--screenshot-dir <dir>] [--palette <rgb,rgb,rgb,rgb>] [--record <file.gif>] \
+             [--rewind-depth <snapshots>] [--rewind-interval <frames>] \
+             [--break <[addr][:hits] [if expr]>] [--watch <start[-end][:r|w|rw]>] [--watch-reg <v0-vf|i>] \
+             [--trace <file>] [--trace-range <start[-end]>] \
+             [--record-movie <file.movie>] [--replay <file.movie>] [--config <file.toml>]",
+            program,
+            Quirks::PRESETS.join("|"),
+            FaultPolicy::NAMES.join("|"),
+            AudioKind::NAMES.join("|"),
+            Scheme::NAMES.join("|"),
+            Glyph::NAMES.join("|"),
+            ColorDepth::NAMES.join("|"),
+            RenderMode::NAMES.join("|")
+        )
+    }
+
+    // positional arguments keep their old meaning, flags can appear anywhere.
+    // Flags override the config file, which is read once the ROM is known.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut cli = Settings::default();
+        let mut config_path: Option<PathBuf> = None;
+        let mut fault_policy = FaultPolicy::default();
+        let mut seed = None;
+        let mut frontend = FrontendKind::default();
+        let mut scale = 10;
+        let mut max_frames = None;
+        let mut state_dir = PathBuf::from("states");
+        let mut screenshot_dir = PathBuf::from("screenshots");
+        let mut record = None;
+        // ten seconds of history at 60 fps
+        let mut rewind_depth = 600;
+        let mut rewind_interval = 1;
+        let mut breakpoints = Vec::new();
+        let mut watchpoints = Vec::new();
+        let mut watch_registers = Vec::new();
+        let mut trace = None;
+        let mut trace_ranges = Vec::new();
+        let mut record_movie = None;
+        let mut replay = None;
+
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--quirks" => {
+                    let name = iter.next().ok_or("--quirks needs a preset name")?;
+                    cli.quirks = Some(
+                        Quirks::from_name(name)
+                            .ok_or_else(|| format!("Unknown quirks preset: {}", name))?,
+                    );
+                }
+                "--xo-chip" => cli.xo_chip = Some(true),
+                "--on-fault" => {
+                    let name = iter.next().ok_or("--on-fault needs a policy")?;
+                    fault_policy = FaultPolicy::from_name(name)
+                        .ok_or_else(|| format!("Unknown fault policy: {}", name))?;
+                }
+                "--seed" => {
+                    let value = iter.next().ok_or("--seed needs a number")?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid seed: {}", value))?,
+                    );
+                }
+                "--audio" => {
+                    let name = iter.next().ok_or("--audio needs a backend name")?;
+                    cli.audio = Some(
+                        AudioKind::from_name(name)
+                            .ok_or_else(|| format!("Unknown audio backend: {}", name))?,
+                    );
+                }
+                "--tone" => {
+                    let value = iter.next().ok_or("--tone needs a frequency")?;
+                    cli.tone_hz = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid tone: {}", value))?,
+                    );
+                }
+                "--volume" => {
+                    let value = iter.next().ok_or("--volume needs a percentage")?;
+                    let percent: f32 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid volume: {}", value))?;
+                    cli.volume = Some(percent.clamp(0.0, 100.0) / 100.0);
+                }
+                "--frontend" => {
+                    frontend = match iter.next().map(String::as_str) {
+                        Some("tui") => FrontendKind::Tui,
+                        Some("sdl") => FrontendKind::Sdl,
+                        Some(name) => return Err(format!("Unknown frontend: {}", name)),
+                        None => return Err("--frontend needs a name".to_string()),
+                    };
+                }
+                "--scale" => {
+                    let value = iter.next().ok_or("--scale needs a number")?;
+                    scale = value
+                        .parse()
+                        .ok()
+                        .filter(|&scale| scale > 0)
+                        .ok_or_else(|| format!("Invalid scale: {}", value))?;
+                }
+                "--frames" => {
+                    let value = iter.next().ok_or("--frames needs a number")?;
+                    max_frames = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid frame count: {}", value))?,
+                    );
+                }
+                "--state-dir" => {
+                    state_dir = iter.next().ok_or("--state-dir needs a directory")?.into();
+                }
+                "--screenshot-dir" => {
+                    screenshot_dir = iter
+                        .next()
+                        .ok_or("--screenshot-dir needs a directory")?
+                        .into();
+                }
+                "--palette" => {
+                    let value = iter.next().ok_or("--palette needs four colours")?;
+                    cli.palette = Some(
+                        screenshot::parse_palette(value)
+                            .ok_or_else(|| format!("Invalid palette: {}", value))?,
+                    );
+                }
+                "--record" => {
+                    record = Some(iter.next().ok_or("--record needs a file name")?.into());
+                }
+                "--rewind-depth" => {
+                    let value = iter.next().ok_or("--rewind-depth needs a number")?;
+                    rewind_depth = value
+                        .parse()
+                        .map_err(|_| format!("Invalid rewind depth: {}", value))?;
+                }
+                "--rewind-interval" => {
+                    let value = iter.next().ok_or("--rewind-interval needs a number")?;
+                    rewind_interval = value
+                        .parse()
+                        .map_err(|_| format!("Invalid rewind interval: {}", value))?;
+                }
+                "--break" => {
+                    let value = iter.next().ok_or("--break needs an address or condition")?;
+                    breakpoints.push(
+                        Breakpoint::parse(value)
+                            .map_err(|e| format!("Invalid breakpoint '{}': {}", value, e))?,
+                    );
+                }
+                "--watch" => {
+                    let value = iter.next().ok_or("--watch needs an address range")?;
+                    watchpoints.push(
+                        Watchpoint::parse(value)
+                            .ok_or_else(|| format!("Invalid watchpoint: {}", value))?,
+                    );
+                }
+                "--watch-reg" => {
+                    let name = iter.next().ok_or("--watch-reg needs a register")?;
+                    watch_registers.push(
+                        Register::from_name(name)
+                            .ok_or_else(|| format!("Unknown register: {}", name))?,
+                    );
+                }
+                "--trace" => {
+                    trace = Some(iter.next().ok_or("--trace needs a file name")?.into());
+                }
+                "--trace-range" => {
+                    let value = iter.next().ok_or("--trace-range needs an address range")?;
+                    trace_ranges.push(
+                        trace::parse_range(value)
+                            .ok_or_else(|| format!("Invalid trace range: {}", value))?,
+                    );
+                }
+                "--record-movie" => {
+                    record_movie = Some(
+                        iter.next()
+                            .ok_or("--record-movie needs a file name")?
+                            .into(),
+                    );
+                }
+                "--replay" => {
+                    replay = Some(iter.next().ok_or("--replay needs a file name")?.into());
+                }
+                "--theme" => {
+                    let name = iter.next().ok_or("--theme needs a theme name")?;
+                    cli.theme = Some(
+                        Scheme::from_name(name)
+                            .ok_or_else(|| format!("Unknown theme: {}", name))?,
+                    );
+                }
+                "--glyph" => {
+                    let name = iter.next().ok_or("--glyph needs a glyph name")?;
+                    cli.glyph = Some(
+                        Glyph::from_name(name).ok_or_else(|| format!("Unknown glyph: {}", name))?,
+                    );
+                }
+                "--colors" => {
+                    let name = iter.next().ok_or("--colors needs a colour depth")?;
+                    cli.colour_depth = Some(
+                        ColorDepth::from_name(name)
+                            .ok_or_else(|| format!("Unknown colour depth: {}", name))?,
+                    );
+                }
+                "--render" => {
+                    let name = iter.next().ok_or("--render needs a mode")?;
+                    cli.render = Some(
+                        RenderMode::from_name(name)
+                            .ok_or_else(|| format!("Unknown render mode: {}", name))?,
+                    );
+                }
+                "--config" => {
+                    config_path = Some(iter.next().ok_or("--config needs a file name")?.into());
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        if record_movie.is_some() && replay.is_some() {
+            return Err("--record-movie and --replay can't be used together".to_string());
+        }
+        let rom_path = positional.first().ok_or("Missing ROM path")?.clone();
+        cli.cycles_per_frame = positional.get(1).and_then(|cycles| cycles.parse().ok());
+
+        let config = match &config_path {
+            Some(path) => Config::load(path),
+            None => Config::load_default(),
+        }
+        .map_err(|e| e.to_string())?;
+        let mut settings = config.for_rom(&rom_path);
+        settings.merge(&cli);
+        // Octo exports XO-CHIP programs with a .xo8 extension
+        let xo_chip = settings.xo_chip.unwrap_or(false) || rom_path.ends_with(".xo8");
+        // screenshots and recordings use the theme's colours too
+        let palette = settings.scheme().palette;
+        let theme = settings.theme();
+
+        Ok(Args {
+            rom_path,
+            cycles_per_frame: settings.cycles_per_frame(),
+            frame_rate: settings.frame_rate(),
+            quirks: settings.quirks(),
+            xo_chip,
+            fault_policy,
+            seed,
+            audio: settings.audio(),
+            keymap: settings.keymap(),
+            theme,
+            render_mode: settings.render.unwrap_or_default(),
+            log_file: settings.log_file(),
+            log_level: settings.log_level(),
+            frontend,
+            scale,
+            max_frames,
+            state_dir,
+            screenshot_dir,
+            palette,
+            record,
+            rewind_depth,
+            rewind_interval,
+            breakpoints,
+            watchpoints,
+            watch_registers,
+            trace,
+            trace_ranges,
+            record_movie,
+            replay,
+        })
+    }
+}
@@ -0,0 +1,531 @@
+// Settings from config files. The global file lives in the XDG config
+// directory ($XDG_CONFIG_HOME/chipate/config.toml, ~/.config/chipate/config.toml
+// without it) and can override any of its settings for one ROM in a
+// [rom.<name>] table, where name is the ROM's file name without extension.
+// Command line flags win over both.
+//
+//   instructions_per_second = 720
+//   quirks = "octo"
+//
+//   [keys]
+//   5 = "i"
+//
+//   [audio]
+//   backend = "bell"
+//
+//   [theme]
+//   name = "amber"
+//   glyph = "rounded"
+//
+//   [log]
+//   level = "info"
+//
+//   [rom.pong]
+//   quirks = { preset = "vip", display_wait = false }
+
+use chipate::audio::{AudioKind, AudioSettings};
+use chipate::keymap::Keymap;
+use chipate::quirks::Quirks;
+use chipate::render::RenderMode;
+use chipate::screenshot::{self, Rgb};
+use chipate::theme::{ColorDepth, Glyph, Scheme, Theme};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const DEFAULT_CYCLES_PER_FRAME: usize = 12;
+pub const DEFAULT_FRAME_RATE: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// One layer of settings, None where the layer doesn't say
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub cycles_per_frame: Option<usize>,
+    pub instructions_per_second: Option<u32>,
+    // timers tick once per frame, so this changes game speed too
+    pub frame_rate: Option<u32>,
+    pub quirks: Option<Quirks>,
+    // single quirks set on top of the preset, in order
+    pub quirk_flags: Vec<(String, bool)>,
+    pub xo_chip: Option<bool>,
+    // keypad key and the keyboard key bound to it
+    pub keys: Vec<(u8, char)>,
+    pub audio: Option<AudioKind>,
+    pub tone_hz: Option<f32>,
+    pub volume: Option<f32>,
+    pub theme: Option<Scheme>,
+    // custom colours on top of the theme
+    pub palette: Option<[Rgb; 4]>,
+    pub border: Option<Rgb>,
+    pub glyph: Option<Glyph>,
+    pub colour_depth: Option<ColorDepth>,
+    pub render: Option<RenderMode>,
+    pub log_file: Option<PathBuf>,
+    pub log_level: Option<tracing::Level>,
+}
+
+impl Settings {
+    // settings from over replace these
+    pub fn merge(&mut self, over: &Settings) {
+        // the speed is set one way or the other, whichever layer is on top
+        if over.cycles_per_frame.is_some() || over.instructions_per_second.is_some() {
+            self.cycles_per_frame = over.cycles_per_frame;
+            self.instructions_per_second = over.instructions_per_second;
+        }
+        self.frame_rate = over.frame_rate.or(self.frame_rate);
+        // a preset starts the quirks over
+        if over.quirks.is_some() {
+            self.quirks = over.quirks;
+            self.quirk_flags.clear();
+        }
+        self.quirk_flags.extend(over.quirk_flags.iter().cloned());
+        self.xo_chip = over.xo_chip.or(self.xo_chip);
+        self.keys.extend(&over.keys);
+        self.audio = over.audio.or(self.audio);
+        self.tone_hz = over.tone_hz.or(self.tone_hz);
+        self.volume = over.volume.or(self.volume);
+        // so does a theme for custom colours
+        if over.theme.is_some() {
+            self.theme = over.theme;
+            self.palette = None;
+            self.border = None;
+        }
+        self.palette = over.palette.or(self.palette);
+        self.border = over.border.or(self.border);
+        self.glyph = over.glyph.or(self.glyph);
+        self.colour_depth = over.colour_depth.or(self.colour_depth);
+        self.render = over.render.or(self.render);
+        self.log_file = over.log_file.clone().or(self.log_file.take());
+        self.log_level = over.log_level.or(self.log_level);
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.frame_rate.unwrap_or(DEFAULT_FRAME_RATE)
+    }
+
+    pub fn cycles_per_frame(&self) -> usize {
+        match (self.cycles_per_frame, self.instructions_per_second) {
+            (Some(cycles), _) => cycles,
+            (None, Some(ips)) => (ips / self.frame_rate()).max(1) as usize,
+            (None, None) => DEFAULT_CYCLES_PER_FRAME,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.quirks.unwrap_or_default();
+        for (name, value) in &self.quirk_flags {
+            quirks.set_flag(name, *value);
+        }
+        quirks
+    }
+
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = Keymap::default();
+        for &(key, c) in &self.keys {
+            keymap.bind(key, c);
+        }
+        keymap
+    }
+
+    pub fn audio(&self) -> AudioSettings {
+        let defaults = AudioSettings::default();
+        AudioSettings {
+            kind: self.audio.unwrap_or(defaults.kind),
+            tone_hz: self.tone_hz.unwrap_or(defaults.tone_hz),
+            volume: self.volume.unwrap_or(defaults.volume),
+        }
+    }
+
+    // the theme with any custom colours applied
+    pub fn scheme(&self) -> Scheme {
+        let mut scheme = self.theme.unwrap_or_default();
+        if let Some(palette) = self.palette {
+            scheme.palette = palette;
+            scheme.keep_background = false;
+        }
+        if let Some(border) = self.border {
+            scheme.border = border;
+        }
+        scheme
+    }
+
+    pub fn theme(&self) -> Theme {
+        let depth = self.colour_depth.unwrap_or_default();
+        self.scheme().theme(depth, self.glyph.unwrap_or_default())
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.log_file.clone().unwrap_or_else(|| "tui.log".into())
+    }
+
+    pub fn log_level(&self) -> tracing::Level {
+        self.log_level.unwrap_or(tracing::Level::DEBUG)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub settings: Settings,
+    // [rom.<name>] tables by ROM file stem
+    pub roms: HashMap<String, Settings>,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("chipate").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    // the file at the default path, nothing if there isn't one
+    pub fn load_default() -> Result<Self, ConfigError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut table: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| ConfigError(e.to_string().trim_end().to_string()))?;
+        let mut roms = HashMap::new();
+        if let Some(value) = table.remove("rom") {
+            for (name, value) in as_table(&value, "rom")? {
+                let context = format!("rom.{}", name);
+                roms.insert(
+                    name.clone(),
+                    settings(as_table(value, &context)?, &context)?,
+                );
+            }
+        }
+        Ok(Config {
+            settings: settings(&table, "")?,
+            roms,
+        })
+    }
+
+    // the global settings with the ROM's overrides on top
+    pub fn for_rom(&self, rom_path: &str) -> Settings {
+        let mut settings = self.settings.clone();
+        let stem = Path::new(rom_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy());
+        if let Some(rom) = stem.and_then(|stem| self.roms.get(stem.as_ref())) {
+            settings.merge(rom);
+        }
+        settings
+    }
+}
+
+fn settings(table: &Table, context: &str) -> Result<Settings, ConfigError> {
+    let mut settings = Settings::default();
+    for (key, value) in table {
+        let name = qualified(context, key);
+        match key.as_str() {
+            "cycles_per_frame" => settings.cycles_per_frame = Some(positive(value, &name)?),
+            "instructions_per_second" => {
+                settings.instructions_per_second = Some(positive(value, &name)?)
+            }
+            "frame_rate" => settings.frame_rate = Some(positive(value, &name)?),
+            "xo_chip" => settings.xo_chip = Some(boolean(value, &name)?),
+            "render" => {
+                let mode = string(value, &name)?;
+                settings.render = Some(RenderMode::from_name(mode).ok_or_else(|| {
+                    ConfigError(format!("{}: unknown render mode '{}'", name, mode))
+                })?);
+            }
+            "quirks" => quirks(value, &name, &mut settings)?,
+            "keys" => {
+                for (key, value) in as_table(value, &name)? {
+                    let keypad = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or_else(|| {
+                            ConfigError(format!("{}: keypad keys are 0-f, not '{}'", name, key))
+                        })?;
+                    let name = qualified(&name, key);
+                    let text = string(value, &name)?;
+                    let mut chars = text.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => settings.keys.push((keypad, c)),
+                        _ => {
+                            return Err(ConfigError(format!(
+                                "{} has to be a single character",
+                                name
+                            )))
+                        }
+                    }
+                }
+            }
+            "audio" => {
+                for (key, value) in as_table(value, &name)? {
+                    let name = qualified(&name, key);
+                    match key.as_str() {
+                        "backend" => {
+                            let backend = string(value, &name)?;
+                            settings.audio =
+                                Some(AudioKind::from_name(backend).ok_or_else(|| {
+                                    ConfigError(format!("{}: unknown backend '{}'", name, backend))
+                                })?);
+                        }
+                        "tone" => settings.tone_hz = Some(number(value, &name)?),
+                        "volume" => {
+                            settings.volume = Some(number(value, &name)?.clamp(0.0, 100.0) / 100.0)
+                        }
+                        _ => return Err(unknown(&name)),
+                    }
+                }
+            }
+            "theme" => {
+                for (key, value) in as_table(value, &name)? {
+                    let name = qualified(&name, key);
+                    match key.as_str() {
+                        "name" => {
+                            let theme = string(value, &name)?;
+                            settings.theme = Some(Scheme::from_name(theme).ok_or_else(|| {
+                                ConfigError(format!("{}: unknown theme '{}'", name, theme))
+                            })?);
+                        }
+                        "palette" => settings.palette = Some(palette(value, &name)?),
+                        "border" => settings.border = Some(colour(value, &name)?),
+                        "glyph" => {
+                            let glyph = string(value, &name)?;
+                            settings.glyph = Some(Glyph::from_name(glyph).ok_or_else(|| {
+                                ConfigError(format!("{}: unknown glyph '{}'", name, glyph))
+                            })?);
+                        }
+                        "colors" => {
+                            let depth = string(value, &name)?;
+                            settings.colour_depth =
+                                Some(ColorDepth::from_name(depth).ok_or_else(|| {
+                                    ConfigError(format!(
+                                        "{}: unknown colour depth '{}'",
+                                        name, depth
+                                    ))
+                                })?);
+                        }
+                        _ => return Err(unknown(&name)),
+                    }
+                }
+            }
+            "log" => {
+                for (key, value) in as_table(value, &name)? {
+                    let name = qualified(&name, key);
+                    match key.as_str() {
+                        "file" => settings.log_file = Some(string(value, &name)?.into()),
+                        "level" => {
+                            let level = string(value, &name)?;
+                            settings.log_level = Some(level.parse().map_err(|_| {
+                                ConfigError(format!("{}: unknown level '{}'", name, level))
+                            })?);
+                        }
+                        _ => return Err(unknown(&name)),
+                    }
+                }
+            }
+            _ => return Err(unknown(&name)),
+        }
+    }
+    Ok(settings)
+}
+
+// a preset name, or a table of single quirks with an optional preset
+fn quirks(value: &Value, name: &str, settings: &mut Settings) -> Result<(), ConfigError> {
+    let preset = |preset: &str| {
+        Quirks::from_name(preset)
+            .ok_or_else(|| ConfigError(format!("{}: unknown preset '{}'", name, preset)))
+    };
+    if let Value::String(name) = value {
+        settings.quirks = Some(preset(name)?);
+        return Ok(());
+    }
+    for (key, value) in as_table(value, name)? {
+        let flag = qualified(name, key);
+        if key == "preset" {
+            settings.quirks = Some(preset(string(value, &flag)?)?);
+        } else if Quirks::default().set_flag(key, false) {
+            settings
+                .quirk_flags
+                .push((key.clone(), boolean(value, &flag)?));
+        } else {
+            return Err(unknown(&flag));
+        }
+    }
+    Ok(())
+}
+
+fn qualified(context: &str, key: &str) -> String {
+    if context.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", context, key)
+    }
+}
+
+fn unknown(name: &str) -> ConfigError {
+    ConfigError(format!("unknown setting {}", name))
+}
+
+fn as_table<'a>(value: &'a Value, name: &str) -> Result<&'a Table, ConfigError> {
+    value
+        .as_table()
+        .ok_or_else(|| ConfigError(format!("{} has to be a table", name)))
+}
+
+fn string<'a>(value: &'a Value, name: &str) -> Result<&'a str, ConfigError> {
+    value
+        .as_str()
+        .ok_or_else(|| ConfigError(format!("{} has to be a string", name)))
+}
+
+fn boolean(value: &Value, name: &str) -> Result<bool, ConfigError> {
+    value
+        .as_bool()
+        .ok_or_else(|| ConfigError(format!("{} has to be true or false", name)))
+}
+
+fn integer<T: TryFrom<i64>>(value: &Value, name: &str) -> Result<T, ConfigError> {
+    value
+        .as_integer()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| ConfigError(format!("{} has to be a positive whole number", name)))
+}
+
+// a speed of 0 would never run anything
+fn positive<T: TryFrom<i64>>(value: &Value, name: &str) -> Result<T, ConfigError> {
+    if value.as_integer() == Some(0) {
+        return Err(ConfigError(format!("{} has to be at least 1", name)));
+    }
+    integer(value, name)
+}
+
+fn number(value: &Value, name: &str) -> Result<f32, ConfigError> {
+    match value {
+        Value::Integer(value) => Ok(*value as f32),
+        Value::Float(value) => Ok(*value as f32),
+        _ => Err(ConfigError(format!("{} has to be a number", name))),
+    }
+}
+
+fn colour(value: &Value, name: &str) -> Result<Rgb, ConfigError> {
+    let text = string(value, name)?;
+    screenshot::parse_rgb(text)
+        .ok_or_else(|| ConfigError(format!("{}: invalid colour '{}'", name, text)))
+}
+
+// four colours, as an array or the comma separated form --palette takes
+fn palette(value: &Value, name: &str) -> Result<[Rgb; 4], ConfigError> {
+    let invalid = || ConfigError(format!("{} needs four RGB hex colours", name));
+    match value {
+        Value::String(text) => screenshot::parse_palette(text).ok_or_else(invalid),
+        Value::Array(colours) => {
+            let colours: Vec<Rgb> = colours
+                .iter()
+                .map(|value| colour(value, name))
+                .collect::<Result<_, _>>()?;
+            colours.try_into().map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        Config::parse(text).unwrap_err().0
+    }
+
+    #[test]
+    fn rejects_zero_speeds() {
+        for key in ["cycles_per_frame", "instructions_per_second", "frame_rate"] {
+            assert_eq!(
+                error(&format!("{} = 0", key)),
+                format!("{} has to be at least 1", key)
+            );
+        }
+        assert_eq!(
+            error("[rom.pong]\ncycles_per_frame = 0"),
+            "rom.pong.cycles_per_frame has to be at least 1"
+        );
+        assert_eq!(
+            error("frame_rate = -1"),
+            "frame_rate has to be a positive whole number"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        for (text, name) in [
+            ("speed = 1", "speed"),
+            ("[audio]\npitch = 1", "audio.pitch"),
+            ("[theme]\nfont = \"x\"", "theme.font"),
+            ("[log]\npath = \"x\"", "log.path"),
+            ("quirks = { wrap = true }", "quirks.wrap"),
+            ("[rom.pong]\nspeed = 1", "rom.pong.speed"),
+        ] {
+            assert_eq!(error(text), format!("unknown setting {}", name));
+        }
+    }
+
+    #[test]
+    fn layers_override_in_order() {
+        let config = Config::parse(
+            "cycles_per_frame = 10
+            frame_rate = 50
+            quirks = { preset = \"vip\", display_wait = false }
+            xo_chip = true
+
+            [rom.pong]
+            instructions_per_second = 600
+            quirks = \"chip48\"",
+        )
+        .unwrap();
+
+        let other = config.for_rom("roms/tetris.ch8");
+        assert_eq!(other.cycles_per_frame(), 10);
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.display_wait = false;
+        assert_eq!(other.quirks(), quirks);
+
+        // the ROM table replaces the speed and the quirks, the rest stays
+        let mut pong = config.for_rom("roms/pong.ch8");
+        assert_eq!(pong.cycles_per_frame(), 12);
+        assert_eq!(pong.quirks(), Quirks::chip48());
+        assert_eq!(pong.xo_chip, Some(true));
+
+        // command line flags go on top
+        let cli = Settings {
+            cycles_per_frame: Some(30),
+            quirk_flags: vec![("clip_sprites".to_string(), false)],
+            ..Settings::default()
+        };
+        pong.merge(&cli);
+        assert_eq!(pong.cycles_per_frame(), 30);
+        assert_eq!(pong.frame_rate(), 50);
+        let mut quirks = Quirks::chip48();
+        quirks.clip_sprites = false;
+        assert_eq!(pong.quirks(), quirks);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::chip_ate::ChipAte;
+use crate::opcodes::Instruction;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+// width of the panel beside the display, in terminal columns
+pub const PANEL_WIDTH: u16 = 48;
+
+// What the debugger panel shows. The runner owns execution state, this only
+// tracks what the user is looking at.
+#[derive(Debug, Default)]
+pub struct DebuggerView {
+    pub visible: bool,
+    // address the disassembly is scrolled to, follows pc when None
+    pub cursor: Option<u16>,
+}
+
+impl DebuggerView {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // moves the cursor by whole instructions, starting from pc
+    pub fn move_cursor(&mut self, chip: &ChipAte, instructions: i32) {
+        let from = self.cursor.unwrap_or(chip.pc) as i32;
+        let to = (from + instructions * 2).clamp(0, chip.memory.len() as i32 - 2);
+        self.cursor = Some(to as u16);
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect, chip: &ChipAte) {
+        let block = Block::default()
+            .title("Debugger")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::White));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let [state_area, code_area] =
+            Layout::vertical([Constraint::Length(10), Constraint::Min(0)]).areas(inner);
+        frame.render_widget(Paragraph::new(state_lines(chip)), state_area);
+        let rows = code_area.height as usize;
+        frame.render_widget(Paragraph::new(self.disassembly(chip, rows)), code_area);
+    }
+
+    // rows instructions around the cursor, pc marked with >, cursor with *,
+    // breakpoints with o
+    fn disassembly(&self, chip: &ChipAte, rows: usize) -> Vec<Line<'static>> {
+        let centre = self.cursor.unwrap_or(chip.pc) as usize;
+        let start = centre.saturating_sub(rows / 2 * 2);
+        (0..rows)
+            .map(|row| start + row * 2)
+            .filter_map(|address| {
+                let opcode = chip.peek_opcode(address as u16)?;
+                let marker = if address == chip.pc as usize {
+                    '>'
+                } else if Some(address as u16) == self.cursor {
+                    '*'
+                } else {
+                    ' '
+                };
+                let breakpoint = if chip.breakpoints.contains(address as u16) {
+                    'o'
+                } else {
+                    ' '
+                };
+                let text = format!(
+                    "{}{} {:03X}  {:04X}  {}",
+                    marker,
+                    breakpoint,
+                    address,
+                    opcode,
+                    Instruction::from_opcode(opcode)
+                );
+                Some(if address == chip.pc as usize {
+                    text.fg(Color::Yellow).into()
+                } else {
+                    text.into()
+                })
+            })
+            .collect()
+    }
+}
+
+fn state_lines(chip: &ChipAte) -> Vec<Line<'static>> {
+    let mut lines: Vec<Line> = chip
+        .v
+        .chunks(4)
+        .enumerate()
+        .map(|(row, regs)| {
+            let text: Vec<String> = regs
+                .iter()
+                .enumerate()
+                .map(|(col, value)| format!("V{:X}={:02X}", row * 4 + col, value))
+                .collect();
+            text.join(" ").into()
+        })
+        .collect();
+    lines.push(format!("I={:03X}  PC={:03X}  SP={}", chip.i, chip.pc, chip.sp).into());
+    let stack: Vec<String> = chip.stack[..chip.sp as usize]
+        .iter()
+        .map(|address| format!("{:03X}", address))
+        .collect();
+    lines.push(format!("stack: {}", stack.join(" ")).into());
+    lines.push(format!("DT={:02X}  ST={:02X}", chip.delay_timer, chip.sound_timer).into());
+    let keys: String = (0..16)
+        .map(|key| {
+            if chip.keypad[key] != 0 {
+                char::from_digit(key as u32, 16)
+                    .unwrap()
+                    .to_ascii_uppercase()
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    lines.push(format!("keys: {}", keys).into());
+    let mut watches: Vec<String> = chip
+        .breakpoints
+        .watchpoints()
+        .iter()
+        .map(|watch| {
+            let mode = match (watch.read, watch.write) {
+                (true, false) => "r",
+                (false, true) => "w",
+                _ => "rw",
+            };
+            format!("{:03X}-{:03X}:{}", watch.start, watch.end, mode)
+        })
+        .collect();
+    watches.extend(chip.breakpoints.registers().iter().map(|r| r.to_string()));
+    watches.extend(
+        chip.breakpoints
+            .conditions()
+            .iter()
+            .map(|breakpoint| format!("if {}", breakpoint.condition_text)),
+    );
+    lines.push(format!("watch: {}", watches.join(" ")).into());
+    lines.push(Line::default());
+    lines
+}
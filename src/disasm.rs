@@ -0,0 +1,335 @@
+// Disassembles a ROM into Octo source.
+//
+// Code is found by following control flow from 0x200: jumps, calls and both
+// sides of every skip. Everything never reached is emitted as data bytes, so
+// the listing always reassembles to the same ROM. Branch targets, subroutines
+// and the addresses loaded into I get generated labels.
+
+use crate::opcodes::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const PROGRAM_START: usize = 0x200;
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    // ordered by priority, an address both called and loaded is a subroutine
+    Main,
+    Subroutine,
+    Branch,
+    Data,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisasmOptions {
+    // F000 nnnn is four bytes and skips step over it
+    pub xo_chip: bool,
+}
+
+struct Disassembler<'a> {
+    rom: &'a [u8],
+    options: DisasmOptions,
+    // start address and length of every instruction reached
+    code: BTreeMap<usize, usize>,
+    labels: BTreeMap<usize, LabelKind>,
+}
+
+pub fn disassemble(rom: &[u8], options: DisasmOptions) -> String {
+    let mut disasm = Disassembler {
+        rom,
+        options,
+        code: BTreeMap::new(),
+        labels: BTreeMap::new(),
+    };
+    disasm.trace();
+    disasm.emit()
+}
+
+impl Disassembler<'_> {
+    fn opcode(&self, address: usize) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_START)?;
+        let high = *self.rom.get(offset)?;
+        let low = *self.rom.get(offset + 1)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    fn in_rom(&self, address: usize) -> bool {
+        (PROGRAM_START..PROGRAM_START + self.rom.len()).contains(&address)
+    }
+
+    fn label(&mut self, address: usize, kind: LabelKind) {
+        if self.in_rom(address) {
+            let entry = self.labels.entry(address).or_insert(kind);
+            *entry = (*entry).min(kind);
+        }
+    }
+
+    // bytes the instruction at address takes, 4 for XO-CHIP's F000 nnnn
+    fn length(&self, address: usize) -> usize {
+        match self.opcode(address) {
+            Some(0xF000) if self.options.xo_chip && self.opcode(address + 2).is_some() => 4,
+            _ => 2,
+        }
+    }
+
+    fn trace(&mut self) {
+        self.label(PROGRAM_START, LabelKind::Main);
+        let mut pending = vec![PROGRAM_START];
+        let mut visited = BTreeSet::new();
+        while let Some(address) = pending.pop() {
+            if !visited.insert(address) {
+                continue;
+            }
+            let Some(opcode) = self.opcode(address) else {
+                continue;
+            };
+            let instruction = Instruction::from_opcode(opcode);
+            let length = self.length(address);
+            let valid = match instruction {
+                Instruction::LoadILong => length == 4,
+                _ => octo_syntax(opcode, &instruction, |_| None).is_some(),
+            };
+            if !valid {
+                // not something a program would run, leave it as data
+                continue;
+            }
+            self.code.insert(address, length);
+            let next = address + length;
+            match instruction {
+                Instruction::Jump { address: target } => {
+                    self.label(target as usize, LabelKind::Branch);
+                    pending.push(target as usize);
+                }
+                Instruction::Call { address: target } => {
+                    self.label(target as usize, LabelKind::Subroutine);
+                    pending.push(target as usize);
+                    pending.push(next);
+                }
+                Instruction::JumpV0 { address: target } => {
+                    // the offset isn't known until runtime, only the table gets a label
+                    self.label(target as usize, LabelKind::Data);
+                }
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::SkipEq { .. }
+                | Instruction::SkipNe { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNeReg { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNoKey { .. } => {
+                    pending.push(next);
+                    pending.push(next + self.length(next));
+                }
+                Instruction::LoadI { address: target } => {
+                    self.label(target as usize, LabelKind::Data);
+                    pending.push(next);
+                }
+                Instruction::LoadILong if length == 4 => {
+                    if let Some(target) = self.opcode(address + 2) {
+                        self.label(target as usize, LabelKind::Data);
+                    }
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    fn label_name(&self, address: usize) -> Option<String> {
+        let name = match self.labels.get(&address)? {
+            LabelKind::Main => "main".to_string(),
+            LabelKind::Subroutine => format!("sub_{:03x}", address),
+            LabelKind::Branch => format!("label_{:03x}", address),
+            LabelKind::Data => format!("data_{:03x}", address),
+        };
+        Some(name)
+    }
+
+    fn emit(&self) -> String {
+        let mut out = String::new();
+        let end = PROGRAM_START + self.rom.len();
+        let mut address = PROGRAM_START;
+        let mut data: Vec<u8> = Vec::new();
+        // Octo puts a jump to main at 0x200 unless main comes first, so even an
+        // empty ROM needs it
+        if self.rom.is_empty() {
+            out.push_str(": main\n");
+        }
+        while address < end {
+            if let Some(name) = self.label_name(address) {
+                flush_data(&mut out, &mut data);
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, ": {}", name);
+            }
+            if let Some(text) = self.instruction_at(address) {
+                flush_data(&mut out, &mut data);
+                let _ = writeln!(out, "\t{}", text);
+                address += self.code[&address];
+            } else {
+                data.push(self.rom[address - PROGRAM_START]);
+                if data.len() == DATA_BYTES_PER_LINE {
+                    flush_data(&mut out, &mut data);
+                }
+                address += 1;
+            }
+        }
+        flush_data(&mut out, &mut data);
+        out
+    }
+
+    // the Octo text for the code at address, unless a label needs to go inside it
+    fn instruction_at(&self, address: usize) -> Option<String> {
+        let &length = self.code.get(&address)?;
+        if (address + 1..address + length).any(|inside| self.labels.contains_key(&inside)) {
+            return None;
+        }
+        let opcode = self.opcode(address)?;
+        let instruction = Instruction::from_opcode(opcode);
+        if length == 4 {
+            let target = self.opcode(address + 2)? as usize;
+            let target = self
+                .label_name(target)
+                .unwrap_or_else(|| format!("{:#06x}", target));
+            return Some(format!("i := long {}", target));
+        }
+        octo_syntax(opcode, &instruction, |target| {
+            self.label_name(target as usize)
+        })
+    }
+}
+
+fn flush_data(out: &mut String, data: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{:#04x}", byte)).collect();
+    let _ = writeln!(out, "\t{}", bytes.join(" "));
+    data.clear();
+}
+
+// Octo source for a single instruction, None for opcodes Octo can't produce
+// from a mnemonic
+fn octo_syntax(
+    opcode: u16,
+    instruction: &Instruction,
+    label: impl Fn(u16) -> Option<String>,
+) -> Option<String> {
+    let target = |address: u16| label(address).unwrap_or_else(|| format!("{:#05x}", address));
+    let text = match *instruction {
+        Instruction::ClearScreen => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+        Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LowRes => "lores".to_string(),
+        Instruction::HighRes => "hires".to_string(),
+        Instruction::Jump { address } => format!("jump {}", target(address)),
+        // a bare label is a call in Octo, anything else has to be spelled out
+        Instruction::Call { address } => match label(address) {
+            Some(name) => name,
+            None => format!(
+                "{:#04x} {:#04x} # call {:#05x}",
+                opcode >> 8,
+                opcode & 0xFF,
+                address
+            ),
+        },
+        // Octo has no skips, "if ... then" skips when the condition is false
+        Instruction::SkipEq { vx, byte } => format!("if v{:x} != {:#04x} then", vx, byte),
+        Instruction::SkipNe { vx, byte } => format!("if v{:x} == {:#04x} then", vx, byte),
+        Instruction::SkipEqReg { vx, vy } => format!("if v{:x} != v{:x} then", vx, vy),
+        Instruction::SkipNeReg { vx, vy } => format!("if v{:x} == v{:x} then", vx, vy),
+        Instruction::SkipKey { vx } => format!("if v{:x} -key then", vx),
+        Instruction::SkipNoKey { vx } => format!("if v{:x} key then", vx),
+        Instruction::SaveRange { vx, vy } => format!("save v{:x} - v{:x}", vx, vy),
+        Instruction::LoadRange { vx, vy } => format!("load v{:x} - v{:x}", vx, vy),
+        Instruction::LoadByte { vx, byte } => format!("v{:x} := {:#04x}", vx, byte),
+        Instruction::AddByte { vx, byte } => format!("v{:x} += {:#04x}", vx, byte),
+        Instruction::LoadReg { vx, vy } => format!("v{:x} := v{:x}", vx, vy),
+        Instruction::Or { vx, vy } => format!("v{:x} |= v{:x}", vx, vy),
+        Instruction::And { vx, vy } => format!("v{:x} &= v{:x}", vx, vy),
+        Instruction::Xor { vx, vy } => format!("v{:x} ^= v{:x}", vx, vy),
+        Instruction::AddReg { vx, vy } => format!("v{:x} += v{:x}", vx, vy),
+        Instruction::Sub { vx, vy } => format!("v{:x} -= v{:x}", vx, vy),
+        Instruction::Shr { vx, vy } => format!("v{:x} >>= v{:x}", vx, vy),
+        Instruction::SubN { vx, vy } => format!("v{:x} =- v{:x}", vx, vy),
+        Instruction::Shl { vx, vy } => format!("v{:x} <<= v{:x}", vx, vy),
+        Instruction::LoadI { address } => format!("i := {}", target(address)),
+        Instruction::JumpV0 { address } => format!("jump0 {}", target(address)),
+        Instruction::Random { vx, byte } => format!("v{:x} := random {:#04x}", vx, byte),
+        Instruction::Draw { vx, vy, n } => format!("sprite v{:x} v{:x} {}", vx, vy, n),
+        // the address word is handled by the caller, on its own it's a bare F000
+        Instruction::LoadILong => return None,
+        // Octo only accepts plane masks 0-3
+        Instruction::SelectPlane { n } if n <= 3 => format!("plane {}", n),
+        Instruction::SelectPlane { .. } => return None,
+        Instruction::LoadAudio => "audio".to_string(),
+        Instruction::LoadDelay { vx } => format!("v{:x} := delay", vx),
+        Instruction::WaitKey { vx } => format!("v{:x} := key", vx),
+        Instruction::SetDelay { vx } => format!("delay := v{:x}", vx),
+        Instruction::SetSound { vx } => format!("buzzer := v{:x}", vx),
+        Instruction::AddI { vx } => format!("i += v{:x}", vx),
+        Instruction::SetPitch { vx } => format!("pitch := v{:x}", vx),
+        Instruction::LoadFont { vx } => format!("i := hex v{:x}", vx),
+        Instruction::LoadBigFont { vx } => format!("i := bighex v{:x}", vx),
+        Instruction::StoreBCD { vx } => format!("bcd v{:x}", vx),
+        Instruction::StoreRegs { vx } => format!("save v{:x}", vx),
+        Instruction::LoadRegs { vx } => format!("load v{:x}", vx),
+        Instruction::StoreFlags { vx } => format!("saveflags v{:x}", vx),
+        Instruction::LoadFlags { vx } => format!("loadflags v{:x}", vx),
+        Instruction::Unknown { .. } => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const ROMS: [(&str, &[u8]); 13] = [
+        ("1dcell", include_bytes!("../roms/1dcell.ch8")),
+        ("br8kout", include_bytes!("../roms/br8kout.ch8")),
+        ("ibm", include_bytes!("../roms/ibm.ch8")),
+        ("invaders", include_bytes!("../roms/invaders.ch8")),
+        ("maze", include_bytes!("../roms/maze.ch8")),
+        ("pong", include_bytes!("../roms/pong.ch8")),
+        ("pong2", include_bytes!("../roms/pong2.ch8")),
+        ("spacejam", include_bytes!("../roms/spacejam.ch8")),
+        ("syzygy", include_bytes!("../roms/syzygy.ch8")),
+        ("test_opcode", include_bytes!("../roms/test_opcode.ch8")),
+        ("tetris", include_bytes!("../roms/tetris.ch8")),
+        ("tictac", include_bytes!("../roms/tictac.ch8")),
+        ("ufo", include_bytes!("../roms/ufo.ch8")),
+    ];
+
+    #[test]
+    fn every_rom_reassembles_to_itself() {
+        for (name, rom) in ROMS {
+            let source = disassemble(rom, DisasmOptions::default());
+            let reassembled = assemble(&source, name).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(reassembled, rom, "{}", name);
+        }
+    }
+
+    #[test]
+    fn labels_branch_targets() {
+        // jump over a data byte to a subroutine call
+        let rom = [0x12, 0x03, 0xAA, 0x22, 0x07, 0x12, 0x05, 0x00, 0xEE];
+        let source = disassemble(&rom, DisasmOptions::default());
+        assert_eq!(assemble(&source, "test.8o").as_deref(), Ok(&rom[..]));
+        assert!(source.contains("0xaa"), "{}", source);
+    }
+
+    #[test]
+    fn xo_chip_long_load_is_one_instruction() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x12, 0x04];
+        let source = disassemble(&rom, DisasmOptions { xo_chip: true });
+        assert!(source.contains("i := long"), "{}", source);
+        assert_eq!(assemble(&source, "test.8o").as_deref(), Ok(&rom[..]));
+    }
+}
@@ -1,4 +1,4 @@
-use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
 use futures_util::StreamExt;
 use log::debug;
 use std::time::Duration;
@@ -7,7 +7,12 @@ use tokio::sync::mpsc;
 #[derive(Debug)]
 pub enum AppEvent {
     Tick,
-    Key { key: KeyCode, pressed: bool },
+    Key {
+        key: KeyCode,
+        modifiers: KeyModifiers,
+        pressed: bool,
+    },
+    Resize,
 }
 
 pub struct AppEventHandler {
@@ -38,7 +43,17 @@ impl AppEventHandler {
                                     KeyEventKind::Release => false,
                                     KeyEventKind::Repeat => continue,
                                 };
-                                if sender.send(AppEvent::Key { key: key_event.code, pressed }).await.is_err() {
+                                let event = AppEvent::Key {
+                                    key: key_event.code,
+                                    modifiers: key_event.modifiers,
+                                    pressed,
+                                };
+                                if sender.send(event).await.is_err() {
+                                    break;
+                                }
+                            },
+                            Some(Ok(Event::Resize(_, _))) => {
+                                if sender.send(AppEvent::Resize).await.is_err() {
                                     break;
                                 }
                             },
@@ -0,0 +1,445 @@
+// Small expression language over machine state, used for breakpoint conditions.
+//
+//   v3 == 0x10 && i >= 0x300 && dt == 0
+//   mem[i+2] != 0
+//
+// Values are u32 with wrapping arithmetic, comparisons and logic give 0 or 1
+// and anything non-zero is true. Expressions are parsed once into a tree and
+// evaluated against a ChipAte every time the breakpoint is reached.
+
+use crate::chip_ate::ChipAte;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Not,
+    BitNot,
+    Neg,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u32),
+    Var(Var),
+    // memory byte at the address, 0 outside memory
+    Mem(Box<Expr>),
+    // 1 while the keypad key is held
+    Key(Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    // 1-based column in the source text
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.len(),
+        };
+        let expr = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(parser.error("unexpected input after expression")),
+        }
+    }
+
+    pub fn eval(&self, chip: &ChipAte) -> u32 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(var) => match var {
+                Var::V(n) => chip.v[*n as usize] as u32,
+                Var::I => chip.i as u32,
+                Var::Pc => chip.pc as u32,
+                Var::Sp => chip.sp as u32,
+                Var::Dt => chip.delay_timer as u32,
+                Var::St => chip.sound_timer as u32,
+            },
+            Expr::Mem(address) => {
+                let address = address.eval(chip) as usize;
+                chip.memory.get(address).copied().unwrap_or(0) as u32
+            }
+            Expr::Key(key) => {
+                let key = key.eval(chip) as usize;
+                chip.keypad.get(key).copied().unwrap_or(0) as u32
+            }
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(chip);
+                match op {
+                    UnOp::Not => (value == 0) as u32,
+                    UnOp::BitNot => !value,
+                    UnOp::Neg => value.wrapping_neg(),
+                }
+            }
+            // && and || only evaluate the right side when they need it
+            Expr::Binary(BinOp::And, lhs, rhs) => {
+                (lhs.eval(chip) != 0 && rhs.eval(chip) != 0) as u32
+            }
+            Expr::Binary(BinOp::Or, lhs, rhs) => {
+                (lhs.eval(chip) != 0 || rhs.eval(chip) != 0) as u32
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(chip), rhs.eval(chip));
+                match op {
+                    BinOp::Eq => (a == b) as u32,
+                    BinOp::Ne => (a != b) as u32,
+                    BinOp::Lt => (a < b) as u32,
+                    BinOp::Le => (a <= b) as u32,
+                    BinOp::Gt => (a > b) as u32,
+                    BinOp::Ge => (a >= b) as u32,
+                    BinOp::BitOr => a | b,
+                    BinOp::BitXor => a ^ b,
+                    BinOp::BitAnd => a & b,
+                    BinOp::Shl => a.checked_shl(b).unwrap_or(0),
+                    BinOp::Shr => a.checked_shr(b).unwrap_or(0),
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    // dividing by zero gives 0 rather than stopping the emulator
+                    BinOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, chip: &ChipAte) -> bool {
+        self.eval(chip) != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(u32),
+    Ident(String),
+    Op(&'static str),
+    Open(char),
+    Close(char),
+}
+
+// longest operators first so "<=" isn't read as "<" then "="
+const OPERATORS: [&str; 22] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos] as char;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            while pos < bytes.len() && (bytes[pos] as char).is_ascii_alphanumeric() {
+                pos += 1;
+            }
+            let text = &source[start..pos];
+            let value = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+                u32::from_str_radix(hex, 16)
+            } else if let Some(binary) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+                u32::from_str_radix(binary, 2)
+            } else {
+                text.parse()
+            };
+            let value = value.map_err(|_| ParseError {
+                column: start + 1,
+                message: format!("invalid number '{}'", text),
+            })?;
+            tokens.push((start, Token::Num(value)));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = pos;
+            while pos < bytes.len()
+                && ((bytes[pos] as char).is_ascii_alphanumeric() || bytes[pos] == b'_')
+            {
+                pos += 1;
+            }
+            tokens.push((start, Token::Ident(source[start..pos].to_ascii_lowercase())));
+        } else if c == '[' {
+            tokens.push((pos, Token::Open('[')));
+            pos += 1;
+        } else if c == ']' {
+            tokens.push((pos, Token::Close(']')));
+            pos += 1;
+        } else if let Some(op) = OPERATORS.iter().find(|op| source[pos..].starts_with(*op)) {
+            let token = match *op {
+                "(" => Token::Open('('),
+                ")" => Token::Close(')'),
+                op => Token::Op(op),
+            };
+            tokens.push((pos, token));
+            pos += op.len();
+        } else {
+            return Err(ParseError {
+                column: pos + 1,
+                message: format!(
+                    "unexpected character '{}'",
+                    source[pos..].chars().next().unwrap_or(c)
+                ),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+// binding power of each binary operator, higher binds tighter (C precedence)
+fn binary_op(op: &str) -> Option<(BinOp, u8)> {
+    Some(match op {
+        "||" => (BinOp::Or, 1),
+        "&&" => (BinOp::And, 2),
+        "|" => (BinOp::BitOr, 3),
+        "^" => (BinOp::BitXor, 4),
+        "&" => (BinOp::BitAnd, 5),
+        "==" => (BinOp::Eq, 6),
+        "!=" => (BinOp::Ne, 6),
+        "<" => (BinOp::Lt, 7),
+        "<=" => (BinOp::Le, 7),
+        ">" => (BinOp::Gt, 7),
+        ">=" => (BinOp::Ge, 7),
+        "<<" => (BinOp::Shl, 8),
+        ">>" => (BinOp::Shr, 8),
+        "+" => (BinOp::Add, 9),
+        "-" => (BinOp::Sub, 9),
+        "*" => (BinOp::Mul, 10),
+        "/" => (BinOp::Div, 10),
+        "%" => (BinOp::Rem, 10),
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // length of the source, for errors at the end of input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        let offset = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.end, |(offset, _)| *offset);
+        ParseError {
+            column: offset + 1,
+            message: message.to_string(),
+        }
+    }
+
+    fn expect_close(&mut self, close: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(Token::Close(c)) if *c == close => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", close))),
+        }
+    }
+
+    // precedence climbing, all binary operators are left associative
+    fn expr(&mut self, min_power: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let Some((op, power)) = binary_op(op) else {
+                break;
+            };
+            if power <= min_power {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(power)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek() {
+            Some(Token::Op("!")) => UnOp::Not,
+            Some(Token::Op("~")) => UnOp::BitNot,
+            Some(Token::Op("-")) => UnOp::Neg,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Open('(')) => {
+                let expr = self.expr(0)?;
+                self.expect_close(')')?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if name == "mem" || name == "key" {
+                    if self.next() != Some(Token::Open('[')) {
+                        self.pos -= 1;
+                        return Err(self.error(&format!("expected '[' after {}", name)));
+                    }
+                    let index = Box::new(self.expr(0)?);
+                    self.expect_close(']')?;
+                    return Ok(if name == "mem" {
+                        Expr::Mem(index)
+                    } else {
+                        Expr::Key(index)
+                    });
+                }
+                variable(&name).map(Expr::Var).ok_or_else(|| {
+                    self.pos = start;
+                    self.error(&format!("unknown name '{}'", name))
+                })
+            }
+            _ => {
+                self.pos = start;
+                Err(self.error("expected a number, register or '('"))
+            }
+        }
+    }
+}
+
+fn variable(name: &str) -> Option<Var> {
+    Some(match name {
+        "i" => Var::I,
+        "pc" => Var::Pc,
+        "sp" => Var::Sp,
+        "dt" => Var::Dt,
+        "st" => Var::St,
+        _ => {
+            let digit = name.strip_prefix('v')?;
+            if digit.len() != 1 {
+                return None;
+            }
+            Var::V(u8::from_str_radix(digit, 16).ok()?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> u32 {
+        let mut chip = ChipAte::new();
+        chip.v[3] = 0x10;
+        chip.i = 0x300;
+        chip.memory[0x302] = 7;
+        chip.keypad[5] = 1;
+        Expr::parse(source).unwrap().eval(&chip)
+    }
+
+    fn error(source: &str) -> String {
+        Expr::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("6 & 3 == 3"), 0);
+        assert_eq!(eval("1 | 2 ^ 3"), 1);
+        assert_eq!(eval("0 || 1 && 0"), 0);
+        assert_eq!(eval("-2 + 3"), 1);
+        assert_eq!(eval("!0 + 1"), 2);
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(eval("10 - 3 - 2"), 5);
+        assert_eq!(eval("64 / 4 / 2"), 8);
+    }
+
+    #[test]
+    fn machine_state() {
+        assert_eq!(eval("v3 == 0x10 && i >= 0x300 && dt == 0"), 1);
+        assert_eq!(eval("mem[i+2]"), 7);
+        assert_eq!(eval("key[5] && !key[6]"), 1);
+        assert_eq!(eval("V3 + 0b1"), 0x11);
+    }
+
+    #[test]
+    fn never_panics() {
+        assert_eq!(eval("1 / 0"), 0);
+        assert_eq!(eval("1 % 0"), 0);
+        assert_eq!(eval("1 << 40"), 0);
+        assert_eq!(eval("0 - 1"), u32::MAX);
+        assert_eq!(eval("mem[0xFFFFFF]"), 0);
+    }
+
+    #[test]
+    fn bad_input() {
+        assert_eq!(
+            error("v3 =="),
+            "column 6: expected a number, register or '('"
+        );
+        assert_eq!(error("(1 + 2"), "column 7: expected ')'");
+        assert_eq!(error("vg == 1"), "column 1: unknown name 'vg'");
+        assert_eq!(error("mem 3"), "column 5: expected '[' after mem");
+        assert_eq!(error("0x1g"), "column 1: invalid number '0x1g'");
+        assert_eq!(error("1 = 2"), "column 3: unexpected character '='");
+        assert_eq!(error("1 2"), "column 3: unexpected input after expression");
+    }
+}
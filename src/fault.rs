@@ -0,0 +1,61 @@
+use std::fmt;
+
+// Everything a ROM can do wrong that the emulator has to survive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    StackOverflow,
+    StackUnderflow,
+    OutOfBounds { address: usize },
+    UnknownOpcode { opcode: u16 },
+    PcOutOfRange { pc: u16 },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::StackOverflow => write!(f, "stack overflow"),
+            EmulatorError::StackUnderflow => write!(f, "stack underflow"),
+            EmulatorError::OutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:#X}", address)
+            }
+            EmulatorError::UnknownOpcode { opcode } => write!(f, "unknown opcode {:#06X}", opcode),
+            EmulatorError::PcOutOfRange { pc } => write!(f, "pc out of range at {:#X}", pc),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+// What the run loop does when a cycle faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    #[default]
+    Halt, // stop executing, keep showing the last frame
+    Skip,  // ignore the faulting instruction and carry on
+    Log,   // like skip, but log the fault
+    Debug, // pause so the machine state can be inspected
+}
+
+impl FaultPolicy {
+    pub const NAMES: [&'static str; 4] = ["halt", "skip", "log", "debug"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "halt" => Some(FaultPolicy::Halt),
+            "skip" => Some(FaultPolicy::Skip),
+            "log" => Some(FaultPolicy::Log),
+            "debug" => Some(FaultPolicy::Debug),
+            _ => None,
+        }
+    }
+
+    // skipping past a pc that is outside memory would only fault again
+    pub fn stops_on(&self, error: &EmulatorError) -> bool {
+        match self {
+            FaultPolicy::Halt | FaultPolicy::Debug => true,
+            FaultPolicy::Skip | FaultPolicy::Log => {
+                matches!(error, EmulatorError::PcOutOfRange { .. })
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use crate::chip_ate::ChipAte;
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key { key: u8, pressed: bool }, // a CHIP-8 keypad key, 0x0-0xF
+    Quit,
+}
+
+// Everything the emulator needs from the outside world. Implemented by the
+// ratatui UI and the SDL window, and by anything embedding the core.
+pub trait Frontend {
+    // draws the current framebuffer, status explains why execution stopped
+    fn present(&mut self, chip: &ChipAte, status: Option<&str>) -> Result<(), Box<dyn Error>>;
+
+    // returns the input that arrived since the last call without blocking
+    fn poll_input(&mut self) -> Result<Vec<InputEvent>, Box<dyn Error>>;
+
+    // called once per frame, the buzzer sounds while sound_timer is non-zero
+    fn play_audio(&mut self, chip: &ChipAte);
+}
@@ -0,0 +1,386 @@
+// Running ROMs without a terminal, for test suites and CI.
+//
+// A HeadlessFrontend replays a key script and draws nothing, so a Runner
+// can be stepped as fast as the machine allows. The final screen is checked
+// against a text bitmap or a hash of the framebuffer.
+
+use crate::chip_ate::{rom_hash, ChipAte};
+use crate::frontend::{Frontend, InputEvent};
+use std::error::Error;
+use std::fmt;
+
+// frames a scripted key stays down unless the script says otherwise
+pub const DEFAULT_HOLD_FRAMES: u64 = 4;
+
+// A keypad key pressed on a given frame and held for a number of frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub hold: u64,
+}
+
+impl KeyPress {
+    // "frame:key" or "frame:key:hold", the key is a keypad digit 0-f
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut parts = spec.split(':');
+        let frame = parts.next()?.parse().ok()?;
+        let key = parts.next()?;
+        let hold = match parts.next() {
+            Some(hold) => hold.parse().ok().filter(|&hold| hold > 0)?,
+            None => DEFAULT_HOLD_FRAMES,
+        };
+        if parts.next().is_some() || key.len() != 1 {
+            return None;
+        }
+        let key = u8::from_str_radix(key, 16).ok()?;
+        Some(KeyPress { frame, key, hold })
+    }
+
+    // a whole script, presses separated by spaces or commas
+    pub fn parse_script(script: &str) -> Result<Vec<Self>, String> {
+        script
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|spec| !spec.is_empty())
+            .map(|spec| {
+                KeyPress::parse(spec).ok_or_else(|| format!("invalid key press '{}'", spec))
+            })
+            .collect()
+    }
+}
+
+// Feeds scripted key presses to a Runner and counts frames; nothing is shown
+// or played
+#[derive(Debug, Default)]
+pub struct HeadlessFrontend {
+    pub presses: Vec<KeyPress>,
+    // frames whose input has been polled
+    pub frame: u64,
+}
+
+impl HeadlessFrontend {
+    pub fn new(presses: Vec<KeyPress>) -> Self {
+        HeadlessFrontend { presses, frame: 0 }
+    }
+}
+
+impl Frontend for HeadlessFrontend {
+    fn present(&mut self, _chip: &ChipAte, _status: Option<&str>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Result<Vec<InputEvent>, Box<dyn Error>> {
+        let frame = self.frame;
+        self.frame += 1;
+        let mut events = Vec::new();
+        // releases first, so a key pressed again on the frame it's let go of
+        // still sees a fresh press
+        for press in &self.presses {
+            if press.frame + press.hold == frame {
+                events.push(InputEvent::Key {
+                    key: press.key,
+                    pressed: false,
+                });
+            }
+        }
+        for press in &self.presses {
+            if press.frame == frame {
+                events.push(InputEvent::Key {
+                    key: press.key,
+                    pressed: true,
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    fn play_audio(&mut self, _chip: &ChipAte) {}
+}
+
+// A whole screen as plane masks, row major. In text form each row is a line:
+// '.' or ' ' for an unlit pixel, '#' (or the '█' render_display uses) for
+// plane 1, and '2' or '3' for the other XO-CHIP plane masks. Short lines are
+// padded with unlit pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<u8>,
+}
+
+impl Screen {
+    pub fn capture(chip: &ChipAte) -> Self {
+        Screen {
+            width: chip.width(),
+            height: chip.height(),
+            cells: chip.frame().to_vec(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rows: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        if rows.is_empty() {
+            return Err("expected screen is empty".to_string());
+        }
+        // editors tend to add a trailing blank line, but unlit bottom rows are
+        // blank too, so only a blank line past the last row goes
+        if matches!(rows.len(), 33 | 65) && rows.last() == Some(&"") {
+            rows.pop();
+        }
+        let height = rows.len();
+        let width = if height > 32 { 128 } else { 64 };
+        if height != 32 && height != 64 {
+            return Err(format!("expected screen has {} rows, not 32 or 64", height));
+        }
+        let mut cells = vec![0; width * height];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if x >= width {
+                    return Err(format!("row {} is wider than {} pixels", y + 1, width));
+                }
+                cells[y * width + x] = match c {
+                    '.' | ' ' => 0,
+                    '#' | '█' | '1' => 1,
+                    '2' => 2,
+                    '3' => 3,
+                    _ => return Err(format!("unexpected '{}' in row {}", c, y + 1)),
+                };
+            }
+        }
+        Ok(Screen {
+            width,
+            height,
+            cells,
+        })
+    }
+
+    // FNV-1a over the dimensions and cells, stable between runs and builds
+    pub fn hash(&self) -> u64 {
+        let mut bytes = vec![self.width as u8, self.height as u8];
+        bytes.extend_from_slice(&self.cells);
+        rom_hash(&bytes)
+    }
+
+    // why this isn't the screen a test run expected, empty when it matches
+    // the bitmap and the hash it's checked against
+    pub fn check(&self, expected: Option<&Screen>, expected_hash: Option<u64>) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Some(expected) = expected {
+            match self.differences(expected) {
+                Some(0) => {}
+                Some(count) => {
+                    failures.push(format!("{} pixels differ from the expected screen", count))
+                }
+                None => failures.push(format!(
+                    "screen is {}x{}, expected {}x{}",
+                    self.width, self.height, expected.width, expected.height
+                )),
+            }
+        }
+        let hash = self.hash();
+        if let Some(expected) = expected_hash.filter(|&expected| expected != hash) {
+            failures.push(format!(
+                "screen hash {:016x}, expected {:016x}",
+                hash, expected
+            ));
+        }
+        failures
+    }
+
+    // pixels that differ, or None when the resolutions don't match
+    pub fn differences(&self, other: &Screen) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        Some(
+            self.cells
+                .iter()
+                .zip(&other.cells)
+                .filter(|(a, b)| a != b)
+                .count(),
+        )
+    }
+}
+
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.cells.chunks(self.width) {
+            let line: String = row
+                .iter()
+                .map(|&cell| match cell {
+                    0 => '.',
+                    1 => '#',
+                    2 => '2',
+                    _ => '3',
+                })
+                .collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::FaultPolicy;
+    use crate::runner::Runner;
+
+    // the IBM logo leaves the bottom rows unlit
+    fn ibm() -> ChipAte {
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(include_bytes!("../roms/ibm.ch8"))
+            .unwrap();
+        for _ in 0..100 {
+            chip.cycle();
+        }
+        chip
+    }
+
+    #[test]
+    fn parses_render_display_with_blank_bottom_rows() {
+        let chip = ibm();
+        let text = chip.render_display();
+        assert!(text.contains('█'));
+        assert!(text.ends_with(&format!("{}\n", " ".repeat(64))));
+        assert_eq!(Screen::parse(&text), Ok(Screen::capture(&chip)));
+    }
+
+    #[test]
+    fn parses_its_own_dump() {
+        let screen = Screen::capture(&ibm());
+        assert_eq!(Screen::parse(&screen.to_string()).as_ref(), Ok(&screen));
+        // with the blank line an editor adds at the end
+        assert_eq!(Screen::parse(&format!("{}\n", screen)), Ok(screen));
+    }
+
+    #[test]
+    fn parses_short_lines_and_planes() {
+        let text = format!("#23\n{}", "\n".repeat(31));
+        let screen = Screen::parse(&text).unwrap();
+        assert_eq!((screen.width, screen.height), (64, 32));
+        assert_eq!(&screen.cells[..4], &[1, 2, 3, 0]);
+        let hires = Screen::parse(&".\n".repeat(64)).unwrap();
+        assert_eq!((hires.width, hires.height), (128, 64));
+    }
+
+    #[test]
+    fn rejects_bad_screens() {
+        assert_eq!(
+            Screen::parse(""),
+            Err("expected screen is empty".to_string())
+        );
+        assert_eq!(
+            Screen::parse(&".\n".repeat(31)),
+            Err("expected screen has 31 rows, not 32 or 64".to_string())
+        );
+        assert_eq!(
+            Screen::parse(&format!("{}\n{}", "#".repeat(65), ".\n".repeat(31))),
+            Err("row 1 is wider than 64 pixels".to_string())
+        );
+        assert_eq!(
+            Screen::parse(&format!("x\n{}", ".\n".repeat(31))),
+            Err("unexpected 'x' in row 1".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_key_scripts() {
+        assert_eq!(
+            KeyPress::parse_script("30:1, 45:a:2"),
+            Ok(vec![
+                KeyPress {
+                    frame: 30,
+                    key: 1,
+                    hold: DEFAULT_HOLD_FRAMES
+                },
+                KeyPress {
+                    frame: 45,
+                    key: 0xA,
+                    hold: 2
+                },
+            ])
+        );
+        for bad in ["30", "30:g", "30:10", "x:1", "30:1:0", "30:1:2:3"] {
+            assert_eq!(
+                KeyPress::parse_script(bad),
+                Err(format!("invalid key press '{}'", bad))
+            );
+        }
+    }
+
+    #[test]
+    fn releases_come_before_presses() {
+        let mut frontend = HeadlessFrontend::new(vec![
+            KeyPress {
+                frame: 0,
+                key: 5,
+                hold: 2,
+            },
+            KeyPress {
+                frame: 2,
+                key: 5,
+                hold: 1,
+            },
+        ]);
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            frames.push(frontend.poll_input().unwrap());
+        }
+        let press = |pressed| InputEvent::Key { key: 5, pressed };
+        assert_eq!(
+            frames,
+            vec![
+                vec![press(true)],
+                vec![],
+                vec![press(false), press(true)],
+                vec![press(false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_a_key_script() {
+        // v0 := key, v1 := key, then loop forever
+        let rom = [0xF0, 0x0A, 0xF1, 0x0A, 0x12, 0x04];
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(&rom).unwrap();
+        let mut runner = Runner::new(chip, 12, FaultPolicy::Halt);
+        let script = KeyPress::parse_script("2:5 10:a").unwrap();
+        let mut frontend = HeadlessFrontend::new(script);
+        while runner.frame_count < 20 {
+            runner.step(&mut frontend).unwrap();
+        }
+        assert_eq!(frontend.frame, 20);
+        assert_eq!(runner.frame_count, 20);
+        assert_eq!((runner.chip.v[0], runner.chip.v[1]), (5, 0xA));
+        assert_eq!(runner.chip.pc, 0x204);
+    }
+
+    #[test]
+    fn checks_the_final_screen() {
+        let screen = Screen::capture(&ibm());
+        assert!(screen.check(Some(&screen), Some(screen.hash())).is_empty());
+
+        let mut changed = screen.clone();
+        changed.cells[0] ^= 1;
+        changed.cells[1] ^= 1;
+        assert_eq!(
+            screen.check(Some(&changed), None),
+            vec!["2 pixels differ from the expected screen"]
+        );
+        assert_eq!(
+            screen.check(None, Some(changed.hash())),
+            vec![format!(
+                "screen hash {:016x}, expected {:016x}",
+                screen.hash(),
+                changed.hash()
+            )]
+        );
+        let hires = Screen::parse(&".\n".repeat(64)).unwrap();
+        assert_eq!(
+            screen.check(Some(&hires), None),
+            vec!["screen is 64x32, expected 128x64"]
+        );
+    }
+}
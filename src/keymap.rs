@@ -0,0 +1,69 @@
+// CHIP-8 keypad      Keyboard
+//  1 2 3 C           1 2 3 4
+//  4 5 6 D           Q W E R
+//  7 8 9 E           A S D F
+//  A 0 B F           Z X C V
+pub fn keypad_for_char(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        '1' => Some(0x1),
+        '2' => Some(0x2),
+        '3' => Some(0x3),
+        '4' => Some(0xC),
+        'q' => Some(0x4),
+        'w' => Some(0x5),
+        'e' => Some(0x6),
+        'r' => Some(0xD),
+        'a' => Some(0x7),
+        's' => Some(0x8),
+        'd' => Some(0x9),
+        'f' => Some(0xE),
+        'z' => Some(0xA),
+        'x' => Some(0x0),
+        'c' => Some(0xB),
+        'v' => Some(0xF),
+        _ => None,
+    }
+}
+
+// Keyboard keys bound to each keypad key, the layout above unless configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Option<char>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap { keys: [None; 16] };
+        for c in "1234qwerasdfzxcv".chars() {
+            if let Some(key) = keypad_for_char(c) {
+                keymap.bind(key, c);
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn keypad_for_char(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.keys
+            .iter()
+            .position(|&bound| bound == Some(c))
+            .map(|key| key as u8)
+    }
+
+    pub fn key_for(&self, key: u8) -> Option<char> {
+        self.keys[key as usize & 0xF]
+    }
+
+    // a keyboard key drives one keypad key, so binding it again moves it
+    pub fn bind(&mut self, key: u8, c: char) {
+        let c = c.to_ascii_lowercase();
+        for bound in &mut self.keys {
+            if *bound == Some(c) {
+                *bound = None;
+            }
+        }
+        self.keys[key as usize & 0xF] = Some(c);
+    }
+}
@@ -0,0 +1,37 @@
+//! chipATE: a CHIP-8, SUPER-CHIP and XO-CHIP emulator core with terminal and
+//! SDL2 frontends. Drive a `ChipAte` directly with `cycle`, or one frame at a
+//! time through a `Runner` and any `Frontend`.
+
+pub mod assembler;
+pub mod audio;
+pub mod breakpoints;
+pub mod chip_ate;
+pub mod debugger;
+pub mod disasm;
+pub mod expr;
+pub mod fault;
+pub mod frontend;
+pub mod headless;
+pub mod keymap;
+pub mod movie;
+pub mod opcodes;
+pub mod quirks;
+pub mod recording;
+pub mod render;
+pub mod rewind;
+pub mod rng;
+pub mod runner;
+pub mod savestate;
+pub mod screenshot;
+#[cfg(feature = "sdl")]
+pub mod sdl_frontend;
+pub mod theme;
+pub mod trace;
+pub mod ui;
+
+pub use chip_ate::{ChipAte, CycleStatus};
+pub use fault::{EmulatorError, FaultPolicy};
+pub use frontend::{Frontend, InputEvent};
+pub use opcodes::Instruction;
+pub use quirks::Quirks;
+pub use runner::{FrameStatus, Runner};
@@ -1,54 +1,185 @@
-use crate::chip_ate::{ChipAte, CycleStatus};
-use crate::ui::UI;
+use crate::cli::{Args, DisasmArgs, FrontendKind, TestArgs, TraceDiffArgs};
+use chipate::assembler;
+use chipate::audio;
+use chipate::disasm::{self, DisasmOptions};
+use chipate::headless::{HeadlessFrontend, Screen};
+use chipate::movie::{Movie, MoviePlayer, MovieRecorder};
+use chipate::recording::GifRecorder;
+use chipate::rewind::RewindBuffer;
+use chipate::runner::RunTarget;
+use chipate::savestate::{self, SLOT_COUNT};
+use chipate::screenshot;
+use chipate::trace::{self, TraceRecorder};
+use chipate::ui::{self, UI};
+use chipate::{ChipAte, FaultPolicy, FrameStatus, Frontend, Runner};
 use crossterm::{
-    event::KeyCode,
+    event::{KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use log::{info, warn};
 use std::env;
 use std::io::stdout;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use tokio::task::LocalSet;
 
-mod chip_ate;
+mod cli;
+mod config;
 mod events;
-mod opcodes;
-mod ui;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let log_file = std::fs::File::create("tui.log")?;
+    let mut args: Vec<String> = env::args().collect();
+    // subcommands write to stdout, so they run before logging is set up
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        return disasm_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("trace-diff") {
+        return trace_diff_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("test") {
+        return test_command(&args);
+    }
+    // "run" is optional, chipATE game.ch8 and chipATE run game.ch8 are the same
+    if args.get(1).map(String::as_str) == Some("run") {
+        args.remove(1);
+    }
+
+    let args = match Args::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", Args::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+
+    let log_file = std::fs::File::create(&args.log_file)
+        .map_err(|e| format!("Failed to create {}: {}", args.log_file.display(), e))?;
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
+        .with_max_level(args.log_level)
         .with_writer(log_file)
         .init();
-    println!("After logging initialization.");
+    let rom_path = &args.rom_path;
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <rom_path> [cycles_per_frame]", args[0]);
+    let mut chip8 = ChipAte::new();
+    chip8.quirks = args.quirks;
+    if let Some(seed) = args.seed {
+        chip8.seed_rng(seed);
+    }
+    // logged so a bug report can include the seed that reproduces it
+    info!("RNG seed: {}", chip8.rng.seed());
+    if args.xo_chip {
+        chip8.enable_xo_chip();
+    }
+    for (address, breakpoint) in &args.breakpoints {
+        chip8.breakpoints.insert(*address, breakpoint.clone());
+    }
+    for &watchpoint in &args.watchpoints {
+        chip8.breakpoints.watch(watchpoint);
+    }
+    for &register in &args.watch_registers {
+        chip8.breakpoints.watch_register(register);
+    }
+    // a replay runs the machine the way the movie was recorded
+    let replay = match &args.replay {
+        Some(path) => {
+            let movie = read_movie(path)?;
+            movie.configure(&mut chip8);
+            Some(movie)
+        }
+        None => None,
+    };
+    if let Err(e) = load_program(&mut chip8, rom_path) {
+        eprintln!("Failed to load ROM: {}", e);
         std::process::exit(1);
     }
-    let rom_path = &args[1];
-    let cycles_per_frame: usize = if args.len() >= 3 {
-        args[2].parse().unwrap_or(12)
-    } else {
-        12
+    let cycles_per_frame = match &replay {
+        Some(movie) => {
+            check_movie_rom(movie, &chip8)?;
+            movie.cycles_per_frame
+        }
+        None => args.cycles_per_frame,
     };
+    let mut movie = match (replay, &args.record_movie) {
+        (Some(movie), _) => Some(MovieMode::Replaying(MoviePlayer::new(movie))),
+        (None, Some(path)) => Some(MovieMode::Recording(
+            MovieRecorder::new(&chip8, cycles_per_frame),
+            path.clone(),
+        )),
+        (None, None) => None,
+    };
+    let mut runner = Runner::new(chip8, cycles_per_frame, args.fault_policy);
+    runner.frame_duration = Duration::from_secs_f64(1.0 / args.frame_rate as f64);
+    if let Some(path) = &args.trace {
+        let mut recorder = TraceRecorder::create(path)
+            .map_err(|e| format!("Failed to create trace {}: {}", path.display(), e))?;
+        for &(start, end) in &args.trace_ranges {
+            recorder.add_range(start, end);
+        }
+        runner.trace = Some(recorder);
+    }
+
+    if args.frontend == FrontendKind::Sdl {
+        if movie.is_some() {
+            eprintln!("Movies are only recorded and replayed in the terminal frontend");
+            std::process::exit(1);
+        }
+        #[cfg(feature = "sdl")]
+        {
+            let mut frontend = chipate::sdl_frontend::SdlFrontend::new(&args.audio, args.scale)?;
+            frontend.keymap = args.keymap;
+            frontend.palette = args.palette;
+            runner.run(&mut frontend, args.max_frames)?;
+            return Ok(());
+        }
+        #[cfg(not(feature = "sdl"))]
+        {
+            eprintln!("chipATE was built without the sdl feature");
+            std::process::exit(1);
+        }
+    }
+
+    let audio = match audio::create_backend(&args.audio) {
+        Ok(backend) => backend,
+        Err(e) => {
+            warn!(
+                "Audio unavailable, falling back to the terminal bell: {}",
+                e
+            );
+            Box::new(audio::BellAudio::default())
+        }
+    };
+    let max_frames = args.max_frames;
+    let state_dir = args.state_dir.clone();
+    let screenshot_dir = args.screenshot_dir.clone();
+    let scale = args.scale;
+    let palette = args.palette;
+    let mut recording = match &args.record {
+        Some(path) => Some((
+            GifRecorder::create(path, &palette, scale)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?,
+            path.clone(),
+        )),
+        None => None,
+    };
+    let rom_name = std::path::Path::new(rom_path)
+        .file_stem()
+        .map_or("chipate".into(), |stem| stem.to_string_lossy().into_owned());
+    let mut rewind = RewindBuffer::new(args.rewind_depth, args.rewind_interval);
+    let mut rewind_key = RewindKey::default();
 
     enable_raw_mode()?;
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = ratatui::backend::CrosstermBackend::new(stdout);
     let terminal = ratatui::Terminal::new(backend)?;
-    let mut ui = UI::new(terminal);
-
-    let mut chip8 = ChipAte::new();
-    if let Err(e) = chip8.load_rom(rom_path) {
-        eprintln!("Failed to load ROM: {}", e);
-        std::process::exit(1);
-    }
+    let mut ui = UI::new(terminal, audio);
+    ui.keymap = args.keymap;
+    ui.theme = args.theme;
+    ui.render_mode = args.render_mode;
 
     let (_shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
@@ -56,9 +187,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     local.spawn_local(async move {
         let mut event_handler = events::AppEventHandler::new(16, shutdown_rx);
 
-        let frame_duration = Duration::from_secs_f64(1.0 / 60.0);
-        let mut last_timer_update = Instant::now();
-        let timer_interval = Duration::from_secs_f64(1.0 / 60.0);
+        let frame_duration = runner.frame_duration;
 
         'main_loop: loop {
             let frame_start = Instant::now();
@@ -69,16 +198,119 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if let Some(app_event) = event {
                     match app_event {
                         events::AppEvent::Tick => {}
-                        events::AppEvent::Key { key, pressed } => {
+                        events::AppEvent::Resize => {
+                            if let Err(e) = ui.resize(&runner.chip) {
+                                eprintln!("UI render error: {:?}", e);
+                            }
+                        }
+                        events::AppEvent::Key {
+                            key,
+                            modifiers,
+                            pressed,
+                        } => {
                             if key == KeyCode::Esc {
                                 break 'main_loop;
                             }
-                            if let Some(mapped_key) = map_key(key) {
+                            if key == KeyCode::Backspace {
+                                rewind_key.event(pressed);
+                                continue;
+                            }
+                            if pressed && debugger_key(&mut runner, &mut ui, key, movie.is_some()) {
+                                continue;
+                            }
+                            // F1-F10 load slots 1-10, Shift+F1-F10 save to them
+                            if let KeyCode::F(slot @ 1..=SLOT_COUNT) = key {
+                                if pressed {
+                                    let notice = if modifiers.contains(KeyModifiers::SHIFT) {
+                                        savestate::save_slot(&runner.chip, &state_dir, slot)
+                                            .map(|_| format!("saved slot {}", slot))
+                                    } else {
+                                        let loaded = savestate::load_slot(
+                                            &mut runner.chip,
+                                            &state_dir,
+                                            slot,
+                                        );
+                                        if loaded.is_ok() {
+                                            // a good state gets a halted machine going again
+                                            runner.stopped = None;
+                                            if let Some(mode) = movie.take() {
+                                                ui.notify(finish_movie(mode, "state loaded"));
+                                            }
+                                        }
+                                        loaded.map(|_| format!("loaded slot {}", slot))
+                                    };
+                                    ui.notify(notice.unwrap_or_else(|e| {
+                                        warn!("Save state slot {}: {}", slot, e);
+                                        format!("slot {}: {}", slot, e)
+                                    }));
+                                }
+                                continue;
+                            }
+                            if key == KeyCode::F(12) {
                                 if pressed {
-                                    chip8.keypad[mapped_key as usize] = 1;
-                                    chip8.pressed_key = Some(mapped_key);
-                                } else {
-                                    chip8.keypad[mapped_key as usize] = 0;
+                                    let path = screenshot_dir.join(format!(
+                                        "{}-{}.png",
+                                        rom_name,
+                                        chrono::Local::now().format("%Y%m%d-%H%M%S")
+                                    ));
+                                    let saved = std::fs::create_dir_all(&screenshot_dir)
+                                        .map_err(|e| e.into())
+                                        .and_then(|_| {
+                                            screenshot::save(&runner.chip, &path, &palette, scale)
+                                        });
+                                    ui.notify(match saved {
+                                        Ok(()) => format!("saved {}", path.display()),
+                                        Err(e) => {
+                                            warn!("Screenshot {}: {}", path.display(), e);
+                                            format!("screenshot failed: {}", e)
+                                        }
+                                    });
+                                }
+                                continue;
+                            }
+                            // F11 starts and stops a GIF recording
+                            if key == KeyCode::F(11) {
+                                if pressed {
+                                    let notice = match recording.take() {
+                                        Some((recorder, path)) => finish_recording(recorder, &path),
+                                        None => {
+                                            let path = screenshot_dir.join(format!(
+                                                "{}-{}.gif",
+                                                rom_name,
+                                                chrono::Local::now().format("%Y%m%d-%H%M%S")
+                                            ));
+                                            let recorder = std::fs::create_dir_all(&screenshot_dir)
+                                                .map_err(|e| e.into())
+                                                .and_then(|_| {
+                                                    GifRecorder::create(&path, &palette, scale)
+                                                });
+                                            match recorder {
+                                                Ok(recorder) => {
+                                                    let notice =
+                                                        format!("recording {}", path.display());
+                                                    recording = Some((recorder, path));
+                                                    notice
+                                                }
+                                                Err(e) => {
+                                                    warn!("Recording {}: {}", path.display(), e);
+                                                    format!("recording failed: {}", e)
+                                                }
+                                            }
+                                        }
+                                    };
+                                    ui.notify(notice);
+                                }
+                                continue;
+                            }
+                            if let Some(mapped_key) = ui::map_key(&ui.keymap, key) {
+                                match &mut movie {
+                                    // the movie has the keypad while it plays
+                                    Some(MovieMode::Replaying(_)) => {}
+                                    Some(MovieMode::Recording(recorder, _)) => {
+                                        recorder.input(mapped_key, pressed);
+                                        runner.chip.set_key(mapped_key, pressed);
+                                    }
+                                    None => runner.chip.set_key(mapped_key, pressed),
                                 }
                             }
                         }
@@ -88,28 +320,87 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
-            for _ in 0..cycles_per_frame {
-                match chip8.cycle() {
-                    CycleStatus::WaitingForKey => break,
-                    CycleStatus::Normal => {}
+            if rewind_key.held() {
+                match rewind.step_back() {
+                    Some(state) => {
+                        if let Err(e) = runner.chip.load_state(&state) {
+                            warn!("Rewind failed: {}", e);
+                        }
+                        runner.stopped = None;
+                        ui.notify(format!("rewinding ({} left)", rewind.len()));
+                        if let Some(mode) = movie.take() {
+                            ui.notify(finish_movie(mode, "rewound"));
+                        }
+                    }
+                    None => ui.notify("rewind history exhausted".to_string()),
+                }
+            } else {
+                let was_running = runner.is_running();
+                if let Some(MovieMode::Replaying(player)) = &mut movie {
+                    if was_running {
+                        for (key, pressed) in player.inputs() {
+                            runner.chip.set_key(key, pressed);
+                        }
+                    }
+                }
+                if runner.run_frame() == FrameStatus::Exited {
+                    break 'main_loop;
+                }
+                if was_running {
+                    match &mut movie {
+                        Some(MovieMode::Recording(recorder, _)) => recorder.end_frame(&runner.chip),
+                        Some(MovieMode::Replaying(player)) => {
+                            if let Err(desync) = player.end_frame(&runner.chip) {
+                                warn!("Replay {}", desync);
+                                ui.notify(format!("replay {}", desync));
+                                runner.paused = true;
+                                runner.pause_reason = Some(format!("replay {}", desync));
+                                ui.debugger.visible = true;
+                                movie = None;
+                            } else if player.finished() {
+                                ui.notify(format!("replay finished after {} frames", player.frame));
+                                movie = None;
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                if runner.is_running() {
+                    rewind.record(&runner.chip);
+                }
+                // a fault under --on-fault debug opens the panel on the faulting instruction
+                if was_running && runner.pause_reason.is_some() {
+                    ui.debugger.visible = true;
                 }
             }
-
-            if last_timer_update.elapsed() >= timer_interval {
-                chip8.update_timers();
-                last_timer_update = Instant::now();
+            if let Some((recorder, path)) = &mut recording {
+                if let Err(e) = recorder.record(&runner.chip) {
+                    warn!("Recording {} stopped: {}", path.display(), e);
+                    ui.notify(format!("recording failed: {}", e));
+                    recording = None;
+                }
             }
-
-            if let Err(e) = ui.render(&chip8.display) {
+            ui.play_audio(&runner.chip);
+            if let Err(e) = ui.present(&runner.chip, runner.status()) {
                 eprintln!("UI render error: {:?}", e);
             }
 
+            if max_frames.is_some_and(|max| runner.frame_count >= max) {
+                break 'main_loop;
+            }
+
             let elapsed = frame_start.elapsed();
             if elapsed < frame_duration {
                 tokio::time::sleep(frame_duration - elapsed).await;
             }
         }
 
+        if let Some((recorder, path)) = recording {
+            info!("{}", finish_recording(recorder, &path));
+        }
+        if let Some(mode) = movie {
+            info!("{}", finish_movie(mode, "quit"));
+        }
         disable_raw_mode().expect("Failed to disable raw mode");
         execute!(ui.terminal.backend_mut(), LeaveAlternateScreen)
             .expect("Failed to leave alternate screen");
@@ -121,24 +412,313 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn map_key(key: KeyCode) -> Option<u8> {
+// .8o files are Octo source and get assembled in memory
+fn load_program(chip: &mut ChipAte, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if path.ends_with(".8o") {
+        let source = std::fs::read_to_string(path)?;
+        let rom = assembler::assemble(&source, path)?;
+        chip.load_rom_bytes(&rom)
+    } else {
+        chip.load_rom(path)
+    }
+}
+
+fn disasm_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match DisasmArgs::parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", DisasmArgs::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+    let rom = std::fs::read(&args.rom_path)?;
+    let options = DisasmOptions {
+        xo_chip: args.xo_chip,
+    };
+    let source = disasm::disassemble(&rom, options);
+    match &args.output {
+        Some(path) => std::fs::write(path, source)?,
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+// Whether the rewind key is held. Rewinding lasts from the press to the
+// release, but most terminals only report presses and their autorepeats, so
+// then it also ends once the repeats stop coming.
+#[derive(Default)]
+struct RewindKey {
+    last_press: Option<Instant>,
+    repeating: bool,
+    // the terminal reports releases, so they alone end a hold
+    releases: bool,
+}
+
+// the first autorepeat comes after the keyboard's repeat delay, typically
+// 250-600 ms, later ones every 30-40 ms
+const REWIND_REPEAT_DELAY: Duration = Duration::from_millis(700);
+const REWIND_REPEAT_GAP: Duration = Duration::from_millis(150);
+
+impl RewindKey {
+    fn event(&mut self, pressed: bool) {
+        if pressed {
+            self.repeating = self.held();
+            self.last_press = Some(Instant::now());
+        } else {
+            self.releases = true;
+            self.last_press = None;
+        }
+    }
+
+    fn held(&self) -> bool {
+        let Some(last_press) = self.last_press else {
+            return false;
+        };
+        let timeout = if self.repeating {
+            REWIND_REPEAT_GAP
+        } else {
+            REWIND_REPEAT_DELAY
+        };
+        self.releases || last_press.elapsed() < timeout
+    }
+}
+
+// a movie being recorded or replayed in the terminal frontend
+enum MovieMode {
+    Recording(MovieRecorder, PathBuf),
+    Replaying(MoviePlayer),
+}
+
+fn read_movie(path: &Path) -> Result<Movie, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Movie::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+fn check_movie_rom(movie: &Movie, chip: &ChipAte) -> Result<(), String> {
+    if movie.rom_hash == chip.rom_hash {
+        Ok(())
+    } else {
+        Err(format!(
+            "movie was recorded with ROM {:016x}, this one is {:016x}",
+            movie.rom_hash, chip.rom_hash
+        ))
+    }
+}
+
+// writes a recorded movie, returns the notice shown when a movie stops
+fn finish_movie(mode: MovieMode, reason: &str) -> String {
+    match mode {
+        MovieMode::Recording(recorder, path) => {
+            let frames = recorder.movie.frames();
+            match std::fs::write(&path, recorder.movie.to_string()) {
+                Ok(()) => format!(
+                    "movie stopped ({}), saved {} ({} frames)",
+                    reason,
+                    path.display(),
+                    frames
+                ),
+                Err(e) => {
+                    warn!("Movie {}: {}", path.display(), e);
+                    format!("saving movie failed: {}", e)
+                }
+            }
+        }
+        MovieMode::Replaying(player) => {
+            format!("replay stopped ({}) at frame {}", reason, player.frame)
+        }
+    }
+}
+
+// the notice shown when a recording stops
+fn finish_recording(recorder: GifRecorder, path: &Path) -> String {
+    let frames = recorder.frames();
+    match recorder.finish() {
+        Ok(()) => format!("saved {} ({} frames)", path.display(), frames),
+        Err(e) => {
+            warn!("Recording {}: {}", path.display(), e);
+            format!("recording failed: {}", e)
+        }
+    }
+}
+
+// runs a ROM with no terminal and checks the final screen, exits with 1 if
+// it doesn't match or the machine halts on a fault
+fn test_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match TestArgs::parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", TestArgs::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+    let mut chip8 = ChipAte::new();
+    chip8.quirks = args.quirks;
+    if let Some(seed) = args.seed {
+        chip8.seed_rng(seed);
+    }
+    if args.xo_chip {
+        chip8.enable_xo_chip();
+    }
+    let mut player = match &args.replay {
+        Some(path) => {
+            let movie = read_movie(path)?;
+            movie.configure(&mut chip8);
+            Some(MoviePlayer::new(movie))
+        }
+        None => None,
+    };
+    load_program(&mut chip8, &args.rom_path)?;
+    let mut cycles_per_frame = args.cycles_per_frame;
+    // five seconds is enough for the IBM logo and the Timendus tests
+    let mut frames = args.frames.unwrap_or(300);
+    if let Some(player) = &player {
+        check_movie_rom(&player.movie, &chip8)?;
+        cycles_per_frame = player.movie.cycles_per_frame;
+        frames = args.frames.unwrap_or(player.movie.frames());
+    }
+    let expected = match &args.expect {
+        Some(path) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Some(Screen::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+        }
+        None => None,
+    };
+
+    let mut runner = Runner::new(chip8, cycles_per_frame, FaultPolicy::Halt);
+    let mut frontend = HeadlessFrontend::new(args.keys.clone());
+    let mut recorder = match &args.record {
+        Some(path) => Some(GifRecorder::create(path, &args.palette, args.scale)?),
+        None => None,
+    };
+    let mut desync = None;
+    while runner.frame_count < frames {
+        if let Some(player) = &mut player {
+            for (key, pressed) in player.inputs() {
+                runner.chip.set_key(key, pressed);
+            }
+        }
+        let status = runner.step(&mut frontend)?;
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&runner.chip)?;
+        }
+        if let Some(player) = &mut player {
+            if let Err(e) = player.end_frame(&runner.chip) {
+                desync = Some(e);
+                break;
+            }
+        }
+        if status != FrameStatus::Running || runner.stopped.is_some() {
+            break;
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    let screen = Screen::capture(&runner.chip);
+    let hash = screen.hash();
+    if let Some(path) = &args.dump {
+        std::fs::write(path, screen.to_string())?;
+    }
+    if let Some(path) = &args.screenshot {
+        screenshot::save(&runner.chip, path, &args.palette, args.scale)?;
+    }
+    let mut failures = Vec::new();
+    if let Some(stopped) = &runner.stopped {
+        failures.push(stopped.clone());
+    }
+    if let Some(desync) = desync {
+        failures.push(format!("replay {}", desync));
+    }
+    failures.extend(screen.check(expected.as_ref(), args.expect_hash));
+
+    if failures.is_empty() {
+        println!(
+            "PASS {} after {} frames, screen hash {:016x}",
+            args.rom_path, runner.frame_count, hash
+        );
+        return Ok(());
+    }
+    println!("FAIL {} after {} frames", args.rom_path, runner.frame_count);
+    for failure in &failures {
+        println!("  {}", failure);
+    }
+    print!("{}", screen);
+    std::process::exit(1);
+}
+
+// prints the first difference between two traces, exits with 1 if there is one
+fn trace_diff_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match TraceDiffArgs::parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", TraceDiffArgs::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+    let open = |path: &std::path::Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    match trace::first_divergence(open(&args.left)?, open(&args.right)?)? {
+        None => {
+            println!("traces match");
+            Ok(())
+        }
+        Some(divergence) => {
+            println!("{}", divergence);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Tab shows the debugger panel and Space pauses; stepping and the cursor only
+// work while the panel is open. Returns true when the key was used.
+fn debugger_key<B: ratatui::backend::Backend>(
+    runner: &mut Runner,
+    ui: &mut UI<B>,
+    key: KeyCode,
+    movie: bool,
+) -> bool {
     match key {
-        KeyCode::Char('1') => Some(0x1),
-        KeyCode::Char('2') => Some(0x2),
-        KeyCode::Char('3') => Some(0x3),
-        KeyCode::Char('4') => Some(0xC),
-        KeyCode::Char('q') => Some(0x4),
-        KeyCode::Char('w') => Some(0x5),
-        KeyCode::Char('e') => Some(0x6),
-        KeyCode::Char('r') => Some(0xD),
-        KeyCode::Char('a') => Some(0x7),
-        KeyCode::Char('s') => Some(0x8),
-        KeyCode::Char('d') => Some(0x9),
-        KeyCode::Char('f') => Some(0xE),
-        KeyCode::Char('z') => Some(0xA),
-        KeyCode::Char('x') => Some(0x0),
-        KeyCode::Char('c') => Some(0xB),
-        KeyCode::Char('v') => Some(0xF),
-        _ => None,
+        KeyCode::Tab => ui.debugger.toggle(),
+        KeyCode::Char(' ') => {
+            runner.toggle_pause();
+            ui.debugger.cursor = None;
+        }
+        _ if !ui.debugger.visible => return false,
+        // movies count input by frame, instructions run outside a frame
+        // would desync them
+        KeyCode::Char('n' | 'o' | 'g') if movie => {
+            ui.notify("no stepping while a movie records or plays".to_string());
+        }
+        KeyCode::Char('n') => {
+            runner.step_instruction();
+            ui.debugger.cursor = None;
+        }
+        KeyCode::Char('o') => {
+            runner.step_over();
+            ui.debugger.cursor = None;
+        }
+        KeyCode::Char('g') => {
+            let pc = ui.debugger.cursor.unwrap_or(runner.chip.pc);
+            runner.run_until(RunTarget { pc, sp: None });
+            ui.debugger.cursor = None;
+        }
+        KeyCode::Char('b') => {
+            let address = ui.debugger.cursor.unwrap_or(runner.chip.pc);
+            let set = runner.chip.breakpoints.toggle(address);
+            let action = if set { "set" } else { "cleared" };
+            ui.notify(format!("breakpoint {} at {:#05X}", action, address));
+        }
+        KeyCode::Up => ui.debugger.move_cursor(&runner.chip, -1),
+        KeyCode::Down => ui.debugger.move_cursor(&runner.chip, 1),
+        _ => return false,
     }
+    true
 }
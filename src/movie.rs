@@ -0,0 +1,455 @@
+// Input movies: keypad events by frame, with everything needed to replay
+// them exactly and a hash of the machine after every frame to catch desyncs.
+//
+//   # chipATE movie v1
+//   rom 9c6a2f3b61c0e0d4
+//   seed 1234
+//   xo-chip false
+//   cycles 12
+//   quirks shift_uses_vy=0 load_store_increments_i=1 vf_reset=0 ...
+//   0 hash 5d0e8c1a2b3c4d5e
+//   1 press 1
+//   1 hash 0f1e2d3c4b5a6978
+//   4 release 1
+//
+// Frames only count while the machine runs, so pausing in the debugger
+// doesn't shift later input. Presses apply before their frame runs, the hash
+// is taken after it.
+
+use crate::chip_ate::{rom_hash, ChipAte};
+use crate::quirks::Quirks;
+use std::fmt;
+
+pub const MOVIE_HEADER: &str = "# chipATE movie v1";
+
+// the hash replays compare, covering everything a save state does
+pub fn state_hash(chip: &ChipAte) -> u64 {
+    rom_hash(&chip.save_state())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieInput {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub xo_chip: bool,
+    pub cycles_per_frame: usize,
+    pub quirks: Quirks,
+    // in frame order
+    pub inputs: Vec<MovieInput>,
+    // the state hash after each frame, indexed by frame
+    pub hashes: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieError {
+    // 1-based, 0 for problems with the whole file
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    // an empty movie for the machine as it is configured now
+    pub fn new(chip: &ChipAte, cycles_per_frame: usize) -> Self {
+        Movie {
+            rom_hash: chip.rom_hash,
+            seed: chip.rng.seed(),
+            xo_chip: chip.xo_chip,
+            cycles_per_frame,
+            quirks: chip.quirks,
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.hashes.len() as u64
+    }
+
+    // sets up a machine the way the movie was recorded, before the ROM loads
+    pub fn configure(&self, chip: &mut ChipAte) {
+        chip.seed_rng(self.seed);
+        chip.quirks = self.quirks;
+        if self.xo_chip {
+            chip.enable_xo_chip();
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim() == MOVIE_HEADER => {}
+            _ => return Err(error(0, "not a chipATE movie")),
+        }
+        let mut rom = None;
+        let mut seed = None;
+        let mut xo_chip = false;
+        let mut cycles_per_frame = None;
+        let mut quirks = Quirks::default();
+        let mut inputs = Vec::new();
+        let mut hashes = Vec::new();
+        let mut last_frame = 0;
+
+        for (index, line) in lines {
+            let number = index + 1;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["rom", hash] => rom = Some(parse_hex(hash, number)?),
+                ["seed", value] => {
+                    seed = Some(value.parse().map_err(|_| error(number, "invalid seed"))?)
+                }
+                ["xo-chip", value] => {
+                    xo_chip = value
+                        .parse()
+                        .map_err(|_| error(number, "invalid xo-chip"))?
+                }
+                ["cycles", value] => {
+                    cycles_per_frame = Some(
+                        value
+                            .parse()
+                            .map_err(|_| error(number, "invalid cycle count"))?,
+                    )
+                }
+                ["quirks", flags @ ..] => {
+                    for flag in flags {
+                        let (name, value) = flag
+                            .split_once('=')
+                            .ok_or_else(|| error(number, "quirks are name=0 or name=1"))?;
+                        let value = match value {
+                            "0" => false,
+                            "1" => true,
+                            _ => return Err(error(number, "quirks are name=0 or name=1")),
+                        };
+                        if !quirks.set_flag(name, value) {
+                            return Err(error(number, &format!("unknown quirk '{}'", name)));
+                        }
+                    }
+                }
+                [frame, event, value] => {
+                    let frame: u64 = frame
+                        .parse()
+                        .map_err(|_| error(number, "invalid frame number"))?;
+                    if frame < last_frame {
+                        return Err(error(number, "frames are out of order"));
+                    }
+                    last_frame = frame;
+                    match *event {
+                        "press" | "release" => {
+                            let key = u8::from_str_radix(value, 16)
+                                .ok()
+                                .filter(|&key| key < 16)
+                                .ok_or_else(|| error(number, "keys are 0-f"))?;
+                            inputs.push(MovieInput {
+                                frame,
+                                key,
+                                pressed: *event == "press",
+                            });
+                        }
+                        "hash" => {
+                            if frame != hashes.len() as u64 {
+                                return Err(error(number, "frame hashes have to be consecutive"));
+                            }
+                            hashes.push(parse_hex(value, number)?);
+                        }
+                        _ => return Err(error(number, &format!("unknown event '{}'", event))),
+                    }
+                }
+                _ => return Err(error(number, "unrecognised line")),
+            }
+        }
+
+        Ok(Movie {
+            rom_hash: rom.ok_or_else(|| error(0, "movie has no rom line"))?,
+            seed: seed.ok_or_else(|| error(0, "movie has no seed line"))?,
+            xo_chip,
+            cycles_per_frame: cycles_per_frame
+                .ok_or_else(|| error(0, "movie has no cycles line"))?,
+            quirks,
+            inputs,
+            hashes,
+        })
+    }
+}
+
+fn error(line: usize, message: &str) -> MovieError {
+    MovieError {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn parse_hex(text: &str, line: usize) -> Result<u64, MovieError> {
+    u64::from_str_radix(text, 16).map_err(|_| error(line, &format!("invalid hash '{}'", text)))
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", MOVIE_HEADER)?;
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "xo-chip {}", self.xo_chip)?;
+        writeln!(f, "cycles {}", self.cycles_per_frame)?;
+        let quirks: Vec<String> = self
+            .quirks
+            .flags()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, *value as u8))
+            .collect();
+        writeln!(f, "quirks {}", quirks.join(" "))?;
+        let mut inputs = self.inputs.iter().peekable();
+        for (frame, hash) in self.hashes.iter().enumerate() {
+            while let Some(input) = inputs.next_if(|input| input.frame <= frame as u64) {
+                let event = if input.pressed { "press" } else { "release" };
+                writeln!(f, "{} {} {:x}", input.frame, event, input.key)?;
+            }
+            writeln!(f, "{} hash {:016x}", frame, hash)?;
+        }
+        for input in inputs {
+            let event = if input.pressed { "press" } else { "release" };
+            writeln!(f, "{} {} {:x}", input.frame, event, input.key)?;
+        }
+        Ok(())
+    }
+}
+
+// Builds a movie from the input a running machine gets
+#[derive(Debug, Clone)]
+pub struct MovieRecorder {
+    pub movie: Movie,
+}
+
+impl MovieRecorder {
+    // start before the first frame runs
+    pub fn new(chip: &ChipAte, cycles_per_frame: usize) -> Self {
+        MovieRecorder {
+            movie: Movie::new(chip, cycles_per_frame),
+        }
+    }
+
+    // input applied to the machine before the next frame
+    pub fn input(&mut self, key: u8, pressed: bool) {
+        self.movie.inputs.push(MovieInput {
+            frame: self.movie.frames(),
+            key,
+            pressed,
+        });
+    }
+
+    // after every frame the machine ran
+    pub fn end_frame(&mut self, chip: &ChipAte) {
+        self.movie.hashes.push(state_hash(chip));
+    }
+}
+
+// The first frame where a replay didn't match the movie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u64,
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "desync at frame {}: state hash {:016x}, movie has {:016x}",
+            self.frame, self.found, self.expected
+        )
+    }
+}
+
+// Feeds a movie's input back and checks the state after every frame
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    pub movie: Movie,
+    pub frame: u64,
+    next_input: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            next_input: 0,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames()
+    }
+
+    // the input to apply before the next frame runs
+    pub fn inputs(&mut self) -> Vec<(u8, bool)> {
+        let mut inputs = Vec::new();
+        while let Some(input) = self.movie.inputs.get(self.next_input) {
+            if input.frame > self.frame {
+                break;
+            }
+            inputs.push((input.key, input.pressed));
+            self.next_input += 1;
+        }
+        inputs
+    }
+
+    // after every frame the machine ran, frames past the end aren't checked
+    pub fn end_frame(&mut self, chip: &ChipAte) -> Result<(), Desync> {
+        let frame = self.frame;
+        self.frame += 1;
+        let Some(&expected) = self.movie.hashes.get(frame as usize) else {
+            return Ok(());
+        };
+        let found = state_hash(chip);
+        if found == expected {
+            Ok(())
+        } else {
+            Err(Desync {
+                frame,
+                expected,
+                found,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::FaultPolicy;
+    use crate::runner::Runner;
+
+    // counts frames with key 5 held in v3, drawing a random byte every loop
+    const ROM: [u8; 12] = [
+        0x61, 0x05, 0xC2, 0xFF, 0xE1, 0x9E, 0x12, 0x00, 0x73, 0x01, 0x12, 0x00,
+    ];
+
+    fn runner(movie: Option<&Movie>) -> Runner {
+        let mut chip = ChipAte::new();
+        chip.seed_rng(42);
+        if let Some(movie) = movie {
+            movie.configure(&mut chip);
+        }
+        chip.load_rom_bytes(&ROM).unwrap();
+        Runner::new(chip, 12, FaultPolicy::Halt)
+    }
+
+    // ten frames with key 5 down for frames 3-5
+    fn record() -> Movie {
+        let mut runner = runner(None);
+        let mut recorder = MovieRecorder::new(&runner.chip, runner.cycles_per_frame);
+        for frame in 0..10 {
+            let input = match frame {
+                3 => Some(true),
+                6 => Some(false),
+                _ => None,
+            };
+            if let Some(pressed) = input {
+                recorder.input(5, pressed);
+                runner.chip.set_key(5, pressed);
+            }
+            runner.run_frame();
+            recorder.end_frame(&runner.chip);
+        }
+        recorder.movie
+    }
+
+    fn replay(movie: Movie) -> Result<u64, Desync> {
+        let mut runner = runner(Some(&movie));
+        let mut player = MoviePlayer::new(movie);
+        while !player.finished() {
+            for (key, pressed) in player.inputs() {
+                runner.chip.set_key(key, pressed);
+            }
+            runner.run_frame();
+            player.end_frame(&runner.chip)?;
+        }
+        Ok(player.frame)
+    }
+
+    fn parse_error(text: &str) -> String {
+        Movie::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let movie = record();
+        assert_eq!(movie.frames(), 10);
+        assert_eq!(movie.inputs.len(), 2);
+        let text = movie.to_string();
+        assert!(text.starts_with(MOVIE_HEADER), "{}", text);
+        assert!(text.contains("3 press 5\n3 hash "), "{}", text);
+        assert_eq!(Movie::parse(&text), Ok(movie));
+    }
+
+    #[test]
+    fn replay_matches() {
+        let movie = Movie::parse(&record().to_string()).unwrap();
+        assert_eq!(replay(movie), Ok(10));
+    }
+
+    #[test]
+    fn replay_catches_desync() {
+        let mut movie = record();
+        // the key goes down a frame late, so frame 3 runs differently
+        movie.inputs[0].frame = 4;
+        let desync = replay(movie).unwrap_err();
+        assert_eq!(desync.frame, 3);
+        assert_ne!(desync.found, desync.expected);
+
+        let mut movie = record();
+        // another seed draws other random bytes from the first frame
+        movie.seed = 43;
+        assert_eq!(replay(movie).unwrap_err().frame, 0);
+    }
+
+    #[test]
+    fn bad_files() {
+        let header = format!("{}\nrom 0\nseed 1\ncycles 12\n", MOVIE_HEADER);
+        assert_eq!(parse_error("rom 0\n"), "not a chipATE movie");
+        assert_eq!(
+            parse_error(&format!("{}\nseed 1\ncycles 12\n", MOVIE_HEADER)),
+            "movie has no rom line"
+        );
+        assert_eq!(
+            parse_error(&format!("{}0 press g\n", header)),
+            "line 5: keys are 0-f"
+        );
+        assert_eq!(
+            parse_error(&format!("{}2 press 1\n1 press 2\n", header)),
+            "line 6: frames are out of order"
+        );
+        assert_eq!(
+            parse_error(&format!("{}1 hash 0\n", header)),
+            "line 5: frame hashes have to be consecutive"
+        );
+        assert_eq!(
+            parse_error(&format!("{}quirks wrap=1\n", header)),
+            "line 5: unknown quirk 'wrap'"
+        );
+        assert_eq!(
+            parse_error(&format!("{}0 jump 1\n", header)),
+            "line 5: unknown event 'jump'"
+        );
+    }
+}
@@ -7,16 +7,28 @@
 //  0x00FF = 0000 0000 1111 1111 Isolate lower 8 bits
 //  0x0FFF = 0000 1111 1111 1111 Isolates lower 12 bits
 
-#[derive(Debug)]
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     ClearScreen,           // 00E0
     Return,                //00EE returns from subroutine by popping from the stack
+    ScrollDown { n: u8 },  //00Cn scrolls the display down by n pixels (SUPER-CHIP)
+    ScrollUp { n: u8 },    //00Dn scrolls the display up by n pixels (XO-CHIP)
+    ScrollRight,           //00FB scrolls the display right by 4 pixels (SUPER-CHIP)
+    ScrollLeft,            //00FC scrolls the display left by 4 pixels (SUPER-CHIP)
+    Exit,                  //00FD exits the interpreter (SUPER-CHIP)
+    LowRes,                //00FE switches to 64x32 lores mode (SUPER-CHIP)
+    HighRes,               //00FF switches to 128x64 hires mode (SUPER-CHIP)
     Jump { address: u16 }, //1nnn sets PC to address nnn
     Call { address: u16 }, //2nnn calls subroutine at address nnn pushes current pc to
     //stack
     SkipEq { vx: u8, byte: u8 }, //3xkk skips next instruction if register Vx equals kk
     SkipNe { vx: u8, byte: u8 }, //4xkk skips next instruction if Vx != kk
     SkipEqReg { vx: u8, vy: u8 }, //5xy0 skips next inst. if Vx == Vy
+    SaveRange { vx: u8, vy: u8 }, //5xy2 stores Vx through Vy at I, I unchanged (XO-CHIP)
+    LoadRange { vx: u8, vy: u8 }, //5xy3 loads Vx through Vy from I, I unchanged (XO-CHIP)
     LoadByte { vx: u8, byte: u8 }, //6xkk loads immed. value kk into register Vx
     AddByte { vx: u8, byte: u8 }, //7xkk + immediate value kk to Vx without carry flag
     LoadReg { vx: u8, vy: u8 },  //8xy0 copies val at Vy into Vx
@@ -26,25 +38,32 @@ pub enum Instruction {
     AddReg { vx: u8, vy: u8 },   //8xy4 adds Vy to vx sets VF to 1 if carry occurs
     Sub { vx: u8, vy: u8 },      //8xy5 - Vy from Vx sets VF to 1 if no borrow
     Shr { vx: u8, vy: u8 },      //8xy6 shifts Vx right by 1 VF gets LSB
-    SubN { vx: u8, vy: u8 },     //8xy7 sets Vx to Vy - Vx, VF is 1 if no borrow Shl { vx: u8 },
-    Shl { vx: u8 },              // 8xyE: Shifts Vx left by 1, VF gets the most significant bit
+    SubN { vx: u8, vy: u8 },     //8xy7 sets Vx to Vy - Vx, VF is 1 if no borrow
+    Shl { vx: u8, vy: u8 },      // 8xyE: Shifts Vx left by 1, VF gets the most significant bit
     SkipNeReg { vx: u8, vy: u8 }, //9xy0 skips next instruct. if Vx != Vy
     LoadI { address: u16 },      //Annn sets index register I to address nnn
-    JumpV0 { address: u16 },     //Bnnn jumps to nnn + value in V0
+    JumpV0 { address: u16 },     //Bnnn jumps to nnn + value in V0 (Bxnn: xnn + Vx)
     Random { vx: u8, byte: u8 }, // Cxkk sets Vx to a random byte ANDed with kk
     Draw { vx: u8, vy: u8, n: u8 }, //Dxyn Draws an n-byte spirt at (Vx, Vy), VF set on
-    //collision
+    //collision, Dxy0 draws a 16x16 sprite (SUPER-CHIP)
     SkipKey { vx: u8 },   //Ex9E skips next instruc if key in Vx is pressed
     SkipNoKey { vx: u8 }, //ExA1 skips next instruc. if key in Vx is not pressed
+    LoadILong,            //F000 nnnn loads the following 16-bit word into I (XO-CHIP)
+    SelectPlane { n: u8 }, //Fn01 selects the drawing planes from bitmask n (XO-CHIP)
+    LoadAudio,            //F002 loads the 16-byte audio pattern from I (XO-CHIP)
     LoadDelay { vx: u8 }, //Fx07 loads delay timer to value in Vx
     WaitKey { vx: u8 },   //Fx0A waits for key press stores value in Vx
     SetDelay { vx: u8 },  //Fx15 sets dealy timer to value in Vx
     SetSound { vx: u8 },  //Fx18 sets sound timer to value in Vx
     AddI { vx: u8 },      //Fx1E adds Vx to I
+    SetPitch { vx: u8 },  //Fx3A sets the audio pattern playback pitch to Vx (XO-CHIP)
     LoadFont { vx: u8 },  //Fx29 Sets I to mem address of the font sprite for digit/Vx
+    LoadBigFont { vx: u8 }, //Fx30 Sets I to the 10-byte big font sprite for Vx (SUPER-CHIP)
     StoreBCD { vx: u8 },  //Fx33 stores binary decimal of Vx at I, I+1 I+2
     StoreRegs { vx: u8 }, //Fx55 stores reg V0 through Vx into mem starting at I
     LoadRegs { vx: u8 },  //Fx65 Loads reg V0 through Vx from Mem starting at I
+    StoreFlags { vx: u8 }, //Fx75 stores V0 through Vx in the RPL user flags (SUPER-CHIP)
+    LoadFlags { vx: u8 },  //Fx85 loads V0 through Vx from the RPL user flags (SUPER-CHIP)
     Unknown { opcode: u16 },
 }
 
@@ -60,9 +79,18 @@ impl Instruction {
         let kk = (opcode & 0xFF) as u8; // 8 bit imed value last nib
 
         match first_nibble {
-            0x0000 => match opcode & 0x00FF {
+            // the second nibble has to be 0 as well, so every opcode decodes to
+            // exactly one instruction and encodes back to itself
+            0x0000 => match opcode {
                 0x00E0 => Instruction::ClearScreen,
                 0x00EE => Instruction::Return,
+                0x00C0..=0x00CF => Instruction::ScrollDown { n },
+                0x00D0..=0x00DF => Instruction::ScrollUp { n },
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
                 _ => Instruction::Unknown { opcode },
             },
             0x1000 => Instruction::Jump {
@@ -71,7 +99,12 @@ impl Instruction {
             0x2000 => Instruction::Call { address: nnn }, // call sub at nn
             0x3000 => Instruction::SkipEq { vx: x, byte: kk }, //skip if Vx == kk
             0x4000 => Instruction::SkipNe { vx: x, byte: kk }, //skip if Vx != kk
-            0x5000 => Instruction::SkipEqReg { vx: x, vy: y },
+            0x5000 => match n {
+                0x0 => Instruction::SkipEqReg { vx: x, vy: y },
+                0x2 => Instruction::SaveRange { vx: x, vy: y }, // save Vx-Vy at I
+                0x3 => Instruction::LoadRange { vx: x, vy: y }, // load Vx-Vy from I
+                _ => Instruction::Unknown { opcode },
+            },
             0x6000 => Instruction::LoadByte { vx: x, byte: kk }, //load kk into Vx
             0x7000 => Instruction::AddByte { vx: x, byte: kk },  //add kk to Vx
             0x8000 => {
@@ -87,7 +120,7 @@ impl Instruction {
                     // (with borrow)
                     0x6 => Instruction::Shr { vx: x, vy: y }, // Vx = Vx >> i
                     0x7 => Instruction::SubN { vx: x, vy: y }, // Vx = Vy - Vx
-                    0xE => Instruction::Shl { vx: x },        // Vx = Vx << i
+                    0xE => Instruction::Shl { vx: x, vy: y }, // Vx = Vx << i
                     _ => Instruction::Unknown { opcode },
                 }
             }
@@ -115,19 +148,402 @@ impl Instruction {
             0xF000 => {
                 // Fxxx opcodes are misc, and identified by last byte
                 match opcode & 0x00FF {
+                    0x0000 if x == 0 => Instruction::LoadILong, // I = next word
+                    0x0001 => Instruction::SelectPlane { n: x }, // plane mask = x
+                    0x0002 if x == 0 => Instruction::LoadAudio, // pattern = 16 bytes at I
                     0x0007 => Instruction::LoadDelay { vx: x }, // Vx = delay timer
                     0x000A => Instruction::WaitKey { vx: x },   // wait for key, store in Vx
                     0x0015 => Instruction::SetDelay { vx: x },  // delay timer = Vx
                     0x0018 => Instruction::SetSound { vx: x },  // sound timer = Vx
                     0x001E => Instruction::AddI { vx: x },      // I = I + Vx
                     0x0029 => Instruction::LoadFont { vx: x },  // I = font sprite for Vx
+                    0x0030 => Instruction::LoadBigFont { vx: x }, // I = big font sprite for Vx
                     0x0033 => Instruction::StoreBCD { vx: x },  // store BCD of Vx
+                    0x003A => Instruction::SetPitch { vx: x },  // pitch = Vx
                     0x0055 => Instruction::StoreRegs { vx: x }, // store V0-Vx in memory
                     0x0065 => Instruction::LoadRegs { vx: x },  //load V0-Vx from memory
+                    0x0075 => Instruction::StoreFlags { vx: x }, // store V0-Vx in user flags
+                    0x0085 => Instruction::LoadFlags { vx: x }, // load V0-Vx from user flags
                     _ => Instruction::Unknown { opcode },
                 }
             }
             _ => Instruction::Unknown { opcode },
         }
     }
+
+    // the opcode this instruction decodes from; LoadILong is only the F000
+    // half, the address is the word after it
+    pub fn encode(&self) -> u16 {
+        let xy = |x: u8, y: u8| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xkk = |x: u8, kk: u8| (x as u16 & 0xF) << 8 | kk as u16;
+        let x = |x: u8| (x as u16 & 0xF) << 8;
+        match *self {
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump { address } => 0x1000 | (address & 0x0FFF),
+            Instruction::Call { address } => 0x2000 | (address & 0x0FFF),
+            Instruction::SkipEq { vx, byte } => 0x3000 | xkk(vx, byte),
+            Instruction::SkipNe { vx, byte } => 0x4000 | xkk(vx, byte),
+            Instruction::SkipEqReg { vx, vy } => 0x5000 | xy(vx, vy),
+            Instruction::SaveRange { vx, vy } => 0x5002 | xy(vx, vy),
+            Instruction::LoadRange { vx, vy } => 0x5003 | xy(vx, vy),
+            Instruction::LoadByte { vx, byte } => 0x6000 | xkk(vx, byte),
+            Instruction::AddByte { vx, byte } => 0x7000 | xkk(vx, byte),
+            Instruction::LoadReg { vx, vy } => 0x8000 | xy(vx, vy),
+            Instruction::Or { vx, vy } => 0x8001 | xy(vx, vy),
+            Instruction::And { vx, vy } => 0x8002 | xy(vx, vy),
+            Instruction::Xor { vx, vy } => 0x8003 | xy(vx, vy),
+            Instruction::AddReg { vx, vy } => 0x8004 | xy(vx, vy),
+            Instruction::Sub { vx, vy } => 0x8005 | xy(vx, vy),
+            Instruction::Shr { vx, vy } => 0x8006 | xy(vx, vy),
+            Instruction::SubN { vx, vy } => 0x8007 | xy(vx, vy),
+            Instruction::Shl { vx, vy } => 0x800E | xy(vx, vy),
+            Instruction::SkipNeReg { vx, vy } => 0x9000 | xy(vx, vy),
+            Instruction::LoadI { address } => 0xA000 | (address & 0x0FFF),
+            Instruction::JumpV0 { address } => 0xB000 | (address & 0x0FFF),
+            Instruction::Random { vx, byte } => 0xC000 | xkk(vx, byte),
+            Instruction::Draw { vx, vy, n } => 0xD000 | xy(vx, vy) | (n as u16 & 0xF),
+            Instruction::SkipKey { vx } => 0xE09E | x(vx),
+            Instruction::SkipNoKey { vx } => 0xE0A1 | x(vx),
+            Instruction::LoadILong => 0xF000,
+            Instruction::SelectPlane { n } => 0xF001 | x(n),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::LoadDelay { vx } => 0xF007 | x(vx),
+            Instruction::WaitKey { vx } => 0xF00A | x(vx),
+            Instruction::SetDelay { vx } => 0xF015 | x(vx),
+            Instruction::SetSound { vx } => 0xF018 | x(vx),
+            Instruction::AddI { vx } => 0xF01E | x(vx),
+            Instruction::SetPitch { vx } => 0xF03A | x(vx),
+            Instruction::LoadFont { vx } => 0xF029 | x(vx),
+            Instruction::LoadBigFont { vx } => 0xF030 | x(vx),
+            Instruction::StoreBCD { vx } => 0xF033 | x(vx),
+            Instruction::StoreRegs { vx } => 0xF055 | x(vx),
+            Instruction::LoadRegs { vx } => 0xF065 | x(vx),
+            Instruction::StoreFlags { vx } => 0xF075 | x(vx),
+            Instruction::LoadFlags { vx } => 0xF085 | x(vx),
+            Instruction::Unknown { opcode } => opcode,
+        }
+    }
+}
+
+// Cowgod style mnemonics, with the SUPER-CHIP and XO-CHIP additions in the
+// same style. The output parses back to the same instruction.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { address } => write!(f, "JP {:#05X}", address),
+            Instruction::Call { address } => write!(f, "CALL {:#05X}", address),
+            Instruction::SkipEq { vx, byte } => write!(f, "SE V{:X}, {:#04X}", vx, byte),
+            Instruction::SkipNe { vx, byte } => write!(f, "SNE V{:X}, {:#04X}", vx, byte),
+            Instruction::SkipEqReg { vx, vy } => write!(f, "SE V{:X}, V{:X}", vx, vy),
+            Instruction::SaveRange { vx, vy } => write!(f, "LD [I], V{:X}-V{:X}", vx, vy),
+            Instruction::LoadRange { vx, vy } => write!(f, "LD V{:X}-V{:X}, [I]", vx, vy),
+            Instruction::LoadByte { vx, byte } => write!(f, "LD V{:X}, {:#04X}", vx, byte),
+            Instruction::AddByte { vx, byte } => write!(f, "ADD V{:X}, {:#04X}", vx, byte),
+            Instruction::LoadReg { vx, vy } => write!(f, "LD V{:X}, V{:X}", vx, vy),
+            Instruction::Or { vx, vy } => write!(f, "OR V{:X}, V{:X}", vx, vy),
+            Instruction::And { vx, vy } => write!(f, "AND V{:X}, V{:X}", vx, vy),
+            Instruction::Xor { vx, vy } => write!(f, "XOR V{:X}, V{:X}", vx, vy),
+            Instruction::AddReg { vx, vy } => write!(f, "ADD V{:X}, V{:X}", vx, vy),
+            Instruction::Sub { vx, vy } => write!(f, "SUB V{:X}, V{:X}", vx, vy),
+            Instruction::Shr { vx, vy } => write!(f, "SHR V{:X}, V{:X}", vx, vy),
+            Instruction::SubN { vx, vy } => write!(f, "SUBN V{:X}, V{:X}", vx, vy),
+            Instruction::Shl { vx, vy } => write!(f, "SHL V{:X}, V{:X}", vx, vy),
+            Instruction::SkipNeReg { vx, vy } => write!(f, "SNE V{:X}, V{:X}", vx, vy),
+            Instruction::LoadI { address } => write!(f, "LD I, {:#05X}", address),
+            Instruction::JumpV0 { address } => write!(f, "JP V0, {:#05X}", address),
+            Instruction::Random { vx, byte } => write!(f, "RND V{:X}, {:#04X}", vx, byte),
+            Instruction::Draw { vx, vy, n } => write!(f, "DRW V{:X}, V{:X}, {}", vx, vy, n),
+            Instruction::SkipKey { vx } => write!(f, "SKP V{:X}", vx),
+            Instruction::SkipNoKey { vx } => write!(f, "SKNP V{:X}", vx),
+            Instruction::LoadILong => write!(f, "LD I, LONG"),
+            Instruction::SelectPlane { n } => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::LoadDelay { vx } => write!(f, "LD V{:X}, DT", vx),
+            Instruction::WaitKey { vx } => write!(f, "LD V{:X}, K", vx),
+            Instruction::SetDelay { vx } => write!(f, "LD DT, V{:X}", vx),
+            Instruction::SetSound { vx } => write!(f, "LD ST, V{:X}", vx),
+            Instruction::AddI { vx } => write!(f, "ADD I, V{:X}", vx),
+            Instruction::SetPitch { vx } => write!(f, "PITCH V{:X}", vx),
+            Instruction::LoadFont { vx } => write!(f, "LD F, V{:X}", vx),
+            Instruction::LoadBigFont { vx } => write!(f, "LD HF, V{:X}", vx),
+            Instruction::StoreBCD { vx } => write!(f, "LD B, V{:X}", vx),
+            Instruction::StoreRegs { vx } => write!(f, "LD [I], V{:X}", vx),
+            Instruction::LoadRegs { vx } => write!(f, "LD V{:X}, [I]", vx),
+            Instruction::StoreFlags { vx } => write!(f, "LD R, V{:X}", vx),
+            Instruction::LoadFlags { vx } => write!(f, "LD V{:X}, R", vx),
+            // anything that doesn't decode is shown as a raw data word
+            Instruction::Unknown { opcode } => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseInstructionError(pub String);
+
+impl fmt::Display for ParseInstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseInstructionError {}
+
+// Parses the mnemonics Display writes, case insensitive, numbers in decimal
+// or 0x hex
+impl FromStr for Instruction {
+    type Err = ParseInstructionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let source = text.trim();
+        let text = source.to_ascii_uppercase();
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+        // operands are comma separated, spaces inside them don't matter
+        let operands: Vec<String> = if rest.trim().is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',')
+                .map(|operand| operand.split_whitespace().collect())
+                .collect()
+        };
+        let ops: Vec<&str> = operands.iter().map(String::as_str).collect();
+        let error = || ParseInstructionError(format!("invalid instruction '{}'", source));
+
+        let instruction = match (mnemonic, ops.as_slice()) {
+            ("CLS", []) => Instruction::ClearScreen,
+            ("RET", []) => Instruction::Return,
+            ("SCD", [n]) => Instruction::ScrollDown { n: nibble(n)? },
+            ("SCU", [n]) => Instruction::ScrollUp { n: nibble(n)? },
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("JP", ["V0", address]) => Instruction::JumpV0 {
+                address: address12(address)?,
+            },
+            ("JP", [address]) => Instruction::Jump {
+                address: address12(address)?,
+            },
+            ("CALL", [address]) => Instruction::Call {
+                address: address12(address)?,
+            },
+            ("SE", [x, y]) if is_register(y) => Instruction::SkipEqReg {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("SE", [x, kk]) => Instruction::SkipEq {
+                vx: register(x)?,
+                byte: byte(kk)?,
+            },
+            ("SNE", [x, y]) if is_register(y) => Instruction::SkipNeReg {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("SNE", [x, kk]) => Instruction::SkipNe {
+                vx: register(x)?,
+                byte: byte(kk)?,
+            },
+            ("LD", ["I", "LONG"]) => Instruction::LoadILong,
+            ("LD", ["I", address]) => Instruction::LoadI {
+                address: address12(address)?,
+            },
+            ("LD", ["DT", x]) => Instruction::SetDelay { vx: register(x)? },
+            ("LD", ["ST", x]) => Instruction::SetSound { vx: register(x)? },
+            ("LD", ["F", x]) => Instruction::LoadFont { vx: register(x)? },
+            ("LD", ["HF", x]) => Instruction::LoadBigFont { vx: register(x)? },
+            ("LD", ["B", x]) => Instruction::StoreBCD { vx: register(x)? },
+            ("LD", ["R", x]) => Instruction::StoreFlags { vx: register(x)? },
+            ("LD", ["[I]", range]) if range.contains('-') => {
+                let (vx, vy) = register_range(range)?;
+                Instruction::SaveRange { vx, vy }
+            }
+            ("LD", ["[I]", x]) => Instruction::StoreRegs { vx: register(x)? },
+            ("LD", [range, "[I]"]) if range.contains('-') => {
+                let (vx, vy) = register_range(range)?;
+                Instruction::LoadRange { vx, vy }
+            }
+            ("LD", [x, "[I]"]) => Instruction::LoadRegs { vx: register(x)? },
+            ("LD", [x, "DT"]) => Instruction::LoadDelay { vx: register(x)? },
+            ("LD", [x, "K"]) => Instruction::WaitKey { vx: register(x)? },
+            ("LD", [x, "R"]) => Instruction::LoadFlags { vx: register(x)? },
+            ("LD", [x, y]) if is_register(y) => Instruction::LoadReg {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("LD", [x, kk]) => Instruction::LoadByte {
+                vx: register(x)?,
+                byte: byte(kk)?,
+            },
+            ("ADD", ["I", x]) => Instruction::AddI { vx: register(x)? },
+            ("ADD", [x, y]) if is_register(y) => Instruction::AddReg {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("ADD", [x, kk]) => Instruction::AddByte {
+                vx: register(x)?,
+                byte: byte(kk)?,
+            },
+            ("OR", [x, y]) => Instruction::Or {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("AND", [x, y]) => Instruction::And {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("XOR", [x, y]) => Instruction::Xor {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("SUB", [x, y]) => Instruction::Sub {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("SUBN", [x, y]) => Instruction::SubN {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            // the single operand form leaves Vy as V0
+            ("SHR", [x]) => Instruction::Shr {
+                vx: register(x)?,
+                vy: 0,
+            },
+            ("SHR", [x, y]) => Instruction::Shr {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("SHL", [x]) => Instruction::Shl {
+                vx: register(x)?,
+                vy: 0,
+            },
+            ("SHL", [x, y]) => Instruction::Shl {
+                vx: register(x)?,
+                vy: register(y)?,
+            },
+            ("RND", [x, kk]) => Instruction::Random {
+                vx: register(x)?,
+                byte: byte(kk)?,
+            },
+            ("DRW", [x, y, n]) => Instruction::Draw {
+                vx: register(x)?,
+                vy: register(y)?,
+                n: nibble(n)?,
+            },
+            ("SKP", [x]) => Instruction::SkipKey { vx: register(x)? },
+            ("SKNP", [x]) => Instruction::SkipNoKey { vx: register(x)? },
+            ("PLANE", [n]) => Instruction::SelectPlane { n: nibble(n)? },
+            ("AUDIO", []) => Instruction::LoadAudio,
+            ("PITCH", [x]) => Instruction::SetPitch { vx: register(x)? },
+            ("DW", [word]) => {
+                let word = number(word, 0xFFFF)?;
+                Instruction::from_opcode(word as u16)
+            }
+            _ => return Err(error()),
+        };
+        Ok(instruction)
+    }
+}
+
+fn is_register(text: &str) -> bool {
+    register(text).is_ok()
+}
+
+fn register(text: &str) -> Result<u8, ParseInstructionError> {
+    text.strip_prefix('V')
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+        .ok_or_else(|| ParseInstructionError(format!("expected a register, found '{}'", text)))
+}
+
+fn register_range(text: &str) -> Result<(u8, u8), ParseInstructionError> {
+    let (x, y) = text
+        .split_once('-')
+        .ok_or_else(|| ParseInstructionError(format!("expected Vx-Vy, found '{}'", text)))?;
+    Ok((register(x)?, register(y)?))
+}
+
+// decimal or 0x hex, at most max
+fn number(text: &str, max: u32) -> Result<u32, ParseInstructionError> {
+    let value = match text.strip_prefix("0X") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    value
+        .filter(|&value| value <= max)
+        .ok_or_else(|| ParseInstructionError(format!("invalid number '{}'", text)))
+}
+
+fn nibble(text: &str) -> Result<u8, ParseInstructionError> {
+    Ok(number(text, 0xF)? as u8)
+}
+
+fn byte(text: &str) -> Result<u8, ParseInstructionError> {
+    Ok(number(text, 0xFF)? as u8)
+}
+
+fn address12(text: &str) -> Result<u16, ParseInstructionError> {
+    Ok(number(text, 0xFFF)? as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_encodes_back_to_itself() {
+        for opcode in 0..=u16::MAX {
+            let instruction = Instruction::from_opcode(opcode);
+            assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn every_mnemonic_parses_back() {
+        for opcode in 0..=u16::MAX {
+            let instruction = Instruction::from_opcode(opcode);
+            let text = instruction.to_string();
+            assert_eq!(text.parse(), Ok(instruction), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_lowercase_and_decimal() {
+        assert_eq!(
+            "ld v3, 200".parse(),
+            Ok(Instruction::LoadByte { vx: 3, byte: 200 })
+        );
+        assert_eq!(
+            "drw va , vb , 0xf".parse(),
+            Ok(Instruction::Draw {
+                vx: 0xA,
+                vy: 0xB,
+                n: 0xF
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_operands() {
+        assert!("LD V3, 256".parse::<Instruction>().is_err());
+        assert!("JP 0x1000".parse::<Instruction>().is_err());
+        assert!("ADD VG, 1".parse::<Instruction>().is_err());
+        assert!("NOP".parse::<Instruction>().is_err());
+    }
 }
@@ -0,0 +1,102 @@
+// Quirks cover the places where CHIP-8 interpreters historically disagree.
+// ROMs were written against whatever interpreter their author had, so the
+// same opcode can need different behavior depending on the game.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool, // 8xy6/8xyE copy Vy into Vx before shifting
+    pub load_store_increments_i: bool, // Fx55/Fx65 leave I at I + x + 1
+    pub vf_reset: bool,      // 8xy1/8xy2/8xy3 reset VF to 0
+    pub clip_sprites: bool,  // sprites clip at the screen edge instead of wrapping
+    pub jump_uses_vx: bool,  // Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub display_wait: bool,  // Dxyn waits for the next frame (vblank)
+}
+
+impl Quirks {
+    pub const PRESETS: [&'static str; 4] = ["vip", "chip48", "schip", "octo"];
+
+    // The original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            vf_reset: true,
+            clip_sprites: true,
+            jump_uses_vx: false,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            vf_reset: false,
+            clip_sprites: true,
+            jump_uses_vx: true,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1, an alias for chip48: it kept CHIP-48's behavior for
+    // every quirk modelled here
+    pub fn superchip() -> Self {
+        Quirks::chip48()
+    }
+
+    // Octo and most modern interpreters
+    pub fn octo() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            vf_reset: false,
+            clip_sprites: false,
+            jump_uses_vx: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "chip8" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" | "superchip" => Some(Quirks::superchip()),
+            "octo" | "modern" | "xochip" => Some(Quirks::octo()),
+            _ => None,
+        }
+    }
+
+    // every quirk by name for files that store a whole profile
+    pub fn flags(&self) -> [(&'static str, bool); 6] {
+        [
+            ("shift_uses_vy", self.shift_uses_vy),
+            ("load_store_increments_i", self.load_store_increments_i),
+            ("vf_reset", self.vf_reset),
+            ("clip_sprites", self.clip_sprites),
+            ("jump_uses_vx", self.jump_uses_vx),
+            ("display_wait", self.display_wait),
+        ]
+    }
+
+    // false for a name flags doesn't list
+    pub fn set_flag(&mut self, name: &str, value: bool) -> bool {
+        let flag = match name {
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "load_store_increments_i" => &mut self.load_store_increments_i,
+            "vf_reset" => &mut self.vf_reset,
+            "clip_sprites" => &mut self.clip_sprites,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "display_wait" => &mut self.display_wait,
+            _ => return false,
+        };
+        *flag = value;
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::octo()
+    }
+}
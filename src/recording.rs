@@ -0,0 +1,106 @@
+// Animated GIF recordings of the framebuffer.
+//
+// Call record once per 60 Hz frame. Frames that look like the previous one
+// aren't written again, the previous frame is shown for longer instead, so a
+// game sitting on a menu costs nothing. GIF delays are in hundredths of a
+// second and browsers slow down anything under two, so screens shown for
+// less than that are dropped; delays are rounded so the total length stays
+// exact.
+
+use crate::chip_ate::{ChipAte, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::headless::Screen;
+use crate::screenshot::{self, Rgb};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+const FRAMES_PER_SECOND: u64 = 60;
+// in hundredths of a second
+const MIN_DELAY: u64 = 2;
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    // size of a hires pixel in the GIF, lores pixels are twice that
+    pixel: usize,
+    // the screen waiting to be written, it's on screen until the next change
+    pending: Option<Screen>,
+    frames: u64,
+    // hundredths of a second written so far
+    written: u64,
+}
+
+impl std::fmt::Debug for GifRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GifRecorder")
+            .field("pixel", &self.pixel)
+            .field("frames", &self.frames)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GifRecorder {
+    // scale is the size of a lores pixel like the SDL window, odd scales
+    // round down so hires pixels stay whole
+    pub fn create(path: &Path, palette: &[Rgb; 4], scale: u32) -> Result<Self, Box<dyn Error>> {
+        let pixel = (scale as usize / 2).max(1);
+        let out = BufWriter::new(File::create(path)?);
+        let (width, height) = (HIRES_WIDTH * pixel, HIRES_HEIGHT * pixel);
+        let mut encoder = gif::Encoder::new(out, width as u16, height as u16, &palette.concat())?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(GifRecorder {
+            encoder,
+            pixel,
+            pending: None,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn record(&mut self, chip: &ChipAte) -> Result<(), Box<dyn Error>> {
+        let screen = Screen::capture(chip);
+        if self.pending.as_ref() != Some(&screen) {
+            if self.elapsed() >= MIN_DELAY {
+                self.flush()?;
+            }
+            self.pending = Some(screen);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // writes the trailer, without it the last screen is lost
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()
+    }
+
+    // hundredths of a second since the last frame written
+    fn elapsed(&self) -> u64 {
+        (self.frames * 100 / FRAMES_PER_SECOND).saturating_sub(self.written)
+    }
+
+    // writes the pending screen, shown until the current frame
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(screen) = self.pending.take() else {
+            return Ok(());
+        };
+        let delay = self.elapsed().clamp(MIN_DELAY, u16::MAX as u64);
+        self.written += delay;
+        let scale = self.pixel * HIRES_WIDTH / screen.width;
+        let pixels = screenshot::scale_pixels(&screen, scale);
+        let frame = gif::Frame {
+            width: (screen.width * scale) as u16,
+            height: (screen.height * scale) as u16,
+            buffer: Cow::Owned(pixels),
+            delay: delay as u16,
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+// Ways of drawing the framebuffer into terminal cells.
+//
+// Terminal cells are about twice as tall as they are wide, so every mode
+// keeps pixels square: block mode gives a pixel two cells side by side, half
+// blocks stack two pixels in one cell and braille packs a 2x4 grid of pixels
+// into each cell as dots. Any mode can also scale pixels up by a whole
+// number, so the picture fills the terminal without distorting.
+
+use crate::theme::Theme;
+use ratatui::{buffer::Buffer, layout::Rect, style::Color, widgets::Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    // the biggest pixels any of the others fit
+    #[default]
+    Auto,
+    Block,
+    HalfBlock,
+    Braille,
+}
+
+impl RenderMode {
+    pub const NAMES: [&'static str; 4] = ["auto", "block", "half", "braille"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Some(RenderMode::Auto),
+            "block" => Some(RenderMode::Block),
+            "half" | "halfblock" | "half-block" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None,
+        }
+    }
+
+    // columns and rows a width x height screen takes, Auto counts as braille
+    pub fn cells(self, width: usize, height: usize) -> (u16, u16) {
+        let (columns, rows) = match self {
+            RenderMode::Block => (width * 2, height),
+            RenderMode::HalfBlock => (width, height.div_ceil(2)),
+            RenderMode::Auto | RenderMode::Braille => (width.div_ceil(2), height.div_ceil(4)),
+        };
+        (columns as u16, rows as u16)
+    }
+
+    // pixel size in braille dots, a cell is two dots wide and four tall
+    fn dots(self) -> usize {
+        match self {
+            RenderMode::Block => 4,
+            RenderMode::HalfBlock => 2,
+            RenderMode::Auto | RenderMode::Braille => 1,
+        }
+    }
+
+    // the mode and scale with the biggest pixels that fit in an area of
+    // columns x rows, None when even scale 1 doesn't. Ties go to the coarser
+    // mode, it has no seams between pixels.
+    pub fn fit(self, width: usize, height: usize, area: (u16, u16)) -> Option<(Self, usize)> {
+        let modes = match self {
+            RenderMode::Auto => vec![
+                RenderMode::Block,
+                RenderMode::HalfBlock,
+                RenderMode::Braille,
+            ],
+            mode => vec![mode],
+        };
+        let mut best: Option<(Self, usize)> = None;
+        for mode in modes {
+            let mut scale = 0;
+            loop {
+                let (columns, rows) = mode.cells(width * (scale + 1), height * (scale + 1));
+                if columns > area.0 || rows > area.1 {
+                    break;
+                }
+                scale += 1;
+            }
+            let size = |(mode, scale): (Self, usize)| mode.dots() * scale;
+            if scale > 0 && best.is_none_or(|best| size((mode, scale)) > size(best)) {
+                best = Some((mode, scale));
+            }
+        }
+        best
+    }
+}
+
+// A framebuffer of plane masks drawn in one mode, cropped to the area it's
+// rendered into
+pub struct Display<'a> {
+    pub pixels: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub theme: &'a Theme,
+    // Auto is drawn as braille
+    pub mode: RenderMode,
+    // each pixel is scale x scale of the mode's pixels
+    pub scale: usize,
+}
+
+impl Display<'_> {
+    // palette index of a pixel of the scaled up screen, unlit outside it
+    fn pixel(&self, x: usize, y: usize) -> usize {
+        let scale = self.scale.max(1);
+        let (x, y) = (x / scale, y / scale);
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] as usize & 0x3
+        } else {
+            0
+        }
+    }
+
+    // the symbol and colours of the cell at column, row of the display
+    fn cell(&self, column: usize, row: usize) -> (String, Color, Color) {
+        let palette = &self.theme.palette;
+        let background = palette[0];
+        match self.mode {
+            RenderMode::Block => {
+                let pixel = self.pixel(column / 2, row);
+                if pixel == 0 {
+                    (" ".into(), background, background)
+                } else {
+                    (self.theme.glyph.symbol().into(), palette[pixel], background)
+                }
+            }
+            RenderMode::HalfBlock => {
+                let (top, bottom) = (self.pixel(column, row * 2), self.pixel(column, row * 2 + 1));
+                // the foreground takes the lit half, Reset isn't a colour it can draw
+                match (top, bottom) {
+                    (0, 0) => (" ".into(), background, background),
+                    (0, _) => ("▄".into(), palette[bottom], background),
+                    (_, 0) => ("▀".into(), palette[top], background),
+                    _ if top == bottom => ("█".into(), palette[top], background),
+                    _ => ("▀".into(), palette[top], palette[bottom]),
+                }
+            }
+            RenderMode::Auto | RenderMode::Braille => {
+                // dot bits of the braille block, by offset in the cell
+                const DOTS: [[u32; 2]; 4] =
+                    [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                let mut bits = 0;
+                // a cell has one colour, the most common lit plane mask wins
+                let mut counts = [0; 4];
+                for (dy, row_dots) in DOTS.iter().enumerate() {
+                    for (dx, dot) in row_dots.iter().enumerate() {
+                        let pixel = self.pixel(column * 2 + dx, row * 4 + dy);
+                        if pixel != 0 {
+                            bits |= dot;
+                            counts[pixel] += 1;
+                        }
+                    }
+                }
+                if bits == 0 {
+                    return (" ".into(), background, background);
+                }
+                let colour = (1..4).max_by_key(|&plane| counts[plane]).unwrap_or(1);
+                let symbol = char::from_u32(0x2800 + bits).unwrap_or('⣿');
+                (symbol.to_string(), palette[colour], background)
+            }
+        }
+    }
+}
+
+impl Widget for Display<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let scale = self.scale.max(1);
+        let (columns, rows) = self.mode.cells(self.width * scale, self.height * scale);
+        for row in 0..rows.min(area.height) {
+            for column in 0..columns.min(area.width) {
+                let (symbol, fg, bg) = self.cell(column as usize, row as usize);
+                buf[(area.x + column, area.y + row)]
+                    .set_symbol(&symbol)
+                    .set_fg(fg)
+                    .set_bg(bg);
+            }
+        }
+    }
+}
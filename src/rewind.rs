@@ -0,0 +1,222 @@
+use crate::chip_ate::ChipAte;
+use std::collections::VecDeque;
+
+// Difference between two consecutive snapshots. Most of a frame's state is
+// unchanged, so the XOR of two states is mostly zero runs.
+#[derive(Debug)]
+enum Delta {
+    Xor(Vec<u8>),
+    // snapshots of different sizes can't be XORed
+    Full(Vec<u8>),
+}
+
+// Ring buffer of periodic save states for stepping the machine backwards.
+// Only the newest snapshot is kept whole, each older one is stored as a
+// delta against the snapshot after it.
+#[derive(Debug)]
+pub struct RewindBuffer {
+    depth: usize,
+    interval: u64,
+    frames_since_snapshot: u64,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    // keeps up to depth snapshots, one every interval frames; depth 0 disables rewinding
+    pub fn new(depth: usize, interval: u64) -> Self {
+        RewindBuffer {
+            depth,
+            interval: interval.max(1),
+            frames_since_snapshot: 0,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    // call once per emulated frame
+    pub fn record(&mut self, chip: &ChipAte) {
+        if self.depth == 0 {
+            return;
+        }
+        self.frames_since_snapshot += 1;
+        if self.latest.is_some() && self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = chip.save_state();
+        if let Some(previous) = self.latest.take() {
+            let delta = if previous.len() == state.len() {
+                Delta::Xor(encode_delta(&state, &previous))
+            } else {
+                Delta::Full(previous)
+            };
+            self.history.push_back(delta);
+            if self.history.len() >= self.depth {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // the snapshot before the newest one, which becomes the newest; None once
+    // the oldest snapshot has been reached
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let delta = self.history.pop_back()?;
+        let latest = self.latest.as_mut()?;
+        match delta {
+            Delta::Xor(delta) => apply_delta(latest, &delta),
+            Delta::Full(state) => *latest = state,
+        }
+        self.frames_since_snapshot = 0;
+        Some(latest.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+}
+
+// XOR of two equal length buffers as runs of [u16 unchanged][u16 changed][changed bytes]
+fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let max_run = u16::MAX as usize;
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < a.len() {
+        let same_start = pos;
+        while pos < a.len() && a[pos] == b[pos] && pos - same_start < max_run {
+            pos += 1;
+        }
+        let changed_start = pos;
+        while pos < a.len() && a[pos] != b[pos] && pos - changed_start < max_run {
+            pos += 1;
+        }
+        out.extend_from_slice(&((changed_start - same_start) as u16).to_le_bytes());
+        out.extend_from_slice(&((pos - changed_start) as u16).to_le_bytes());
+        out.extend(
+            a[changed_start..pos]
+                .iter()
+                .zip(&b[changed_start..pos])
+                .map(|(x, y)| x ^ y),
+        );
+    }
+    out
+}
+
+fn apply_delta(base: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut rest = delta;
+    while rest.len() >= 4 {
+        let same = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let changed = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        pos += same;
+        for (byte, x) in base[pos..pos + changed]
+            .iter_mut()
+            .zip(&rest[4..4 + changed])
+        {
+            *byte ^= x;
+        }
+        pos += changed;
+        rest = &rest[4 + changed..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v0 := random 0xFF, save v0 at i, i += 1, jump back
+    const PROGRAM: [u8; 8] = [0xC0, 0xFF, 0xF0, 0x55, 0x70, 0x01, 0x12, 0x00];
+
+    fn chip(xo_chip: bool) -> ChipAte {
+        let mut chip = ChipAte::new();
+        if xo_chip {
+            chip.enable_xo_chip();
+        }
+        chip.load_rom_bytes(&PROGRAM).unwrap();
+        chip.seed_rng(1);
+        chip.i = 0x300;
+        chip
+    }
+
+    fn frame(chip: &mut ChipAte) {
+        for _ in 0..8 {
+            chip.cycle();
+        }
+        chip.update_timers();
+    }
+
+    #[test]
+    fn steps_back_through_recorded_frames() {
+        let mut chip = chip(false);
+        let mut rewind = RewindBuffer::new(5, 1);
+        let mut snapshots = Vec::new();
+        for _ in 0..10 {
+            frame(&mut chip);
+            rewind.record(&chip);
+            snapshots.push(chip.save_state());
+        }
+        // only the newest 5 are kept
+        assert_eq!(rewind.len(), 5);
+        for expected in snapshots[5..9].iter().rev() {
+            assert_eq!(rewind.step_back().as_ref(), Some(expected));
+        }
+        assert_eq!(rewind.step_back(), None);
+        assert_eq!(rewind.len(), 1);
+
+        // recording carries on from the restored state
+        chip.load_state(&snapshots[5]).unwrap();
+        frame(&mut chip);
+        rewind.record(&chip);
+        assert_eq!(rewind.step_back(), Some(snapshots[5].clone()));
+    }
+
+    #[test]
+    fn records_every_interval_frames() {
+        let mut chip = chip(false);
+        let mut rewind = RewindBuffer::new(10, 3);
+        let mut snapshots = Vec::new();
+        for _ in 0..9 {
+            frame(&mut chip);
+            rewind.record(&chip);
+            snapshots.push(chip.save_state());
+        }
+        // frames 1, 4 and 7
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.step_back(), Some(snapshots[3].clone()));
+        assert_eq!(rewind.step_back(), Some(snapshots[0].clone()));
+        assert_eq!(rewind.step_back(), None);
+    }
+
+    #[test]
+    fn steps_back_over_a_memory_size_change() {
+        let mut chip = chip(false);
+        let mut rewind = RewindBuffer::new(4, 1);
+        frame(&mut chip);
+        rewind.record(&chip);
+        let small = chip.save_state();
+
+        let mut chip = self::chip(true);
+        frame(&mut chip);
+        rewind.record(&chip);
+        let big = chip.save_state();
+        frame(&mut chip);
+        rewind.record(&chip);
+
+        assert_eq!(rewind.step_back(), Some(big));
+        assert_eq!(rewind.step_back(), Some(small));
+    }
+
+    #[test]
+    fn depth_zero_records_nothing() {
+        let mut rewind = RewindBuffer::new(0, 1);
+        rewind.record(&chip(false));
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.step_back(), None);
+    }
+}
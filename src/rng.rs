@@ -0,0 +1,55 @@
+// Random source for Cxkk. It remembers its seed and how many bytes it has
+// handed out, so a run can be reproduced from the seed alone and the exact
+// position in the sequence can be restored later.
+//
+// The generator is SplitMix64, which is counter based: byte n only depends on
+// the seed and n, so restoring a position costs the same however long the
+// session has been running. Rewind restores a state every frame it goes back.
+#[derive(Debug, Clone)]
+pub struct ChipRng {
+    seed: u64,
+    draws: u64,
+}
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+impl ChipRng {
+    pub fn new(seed: u64) -> Self {
+        ChipRng { seed, draws: 0 }
+    }
+
+    // picks a fresh seed, for runs that don't ask for a specific one
+    pub fn from_entropy() -> Self {
+        ChipRng::new(rand::random())
+    }
+
+    // the generator at the same point in its sequence
+    pub fn restore(seed: u64, draws: u64) -> Self {
+        ChipRng { seed, draws }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.draws = self.draws.wrapping_add(1);
+        let mut z = self
+            .seed
+            .wrapping_add(self.draws.wrapping_mul(GOLDEN_GAMMA));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        // the high bits are the best mixed
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+impl Default for ChipRng {
+    fn default() -> Self {
+        ChipRng::from_entropy()
+    }
+}
@@ -0,0 +1,319 @@
+use crate::breakpoints::BreakReason;
+use crate::chip_ate::{ChipAte, CycleStatus};
+use crate::fault::FaultPolicy;
+use crate::frontend::{Frontend, InputEvent};
+use crate::opcodes::Instruction;
+use crate::trace::{TraceEntry, TraceRecorder};
+use log::{error, info, warn};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameStatus {
+    Running,
+    Exited, // the ROM ran 00FD
+    Quit,   // the frontend asked to stop
+}
+
+// Where a paused machine should run to before pausing again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunTarget {
+    pub pc: u16,
+    // only stop at this stack depth, so step over ignores recursive calls
+    pub sp: Option<u8>,
+}
+
+// Runs the machine one 60 Hz frame at a time, shared by every frontend
+#[derive(Debug)]
+pub struct Runner {
+    pub chip: ChipAte,
+    pub cycles_per_frame: usize,
+    pub fault_policy: FaultPolicy,
+    // set once a fault stops execution, the last frame stays on screen until quit
+    pub stopped: Option<String>,
+    // paused machines only move through step_instruction, step_over and run_to
+    pub paused: bool,
+    pub pause_reason: Option<String>,
+    pub run_to: Option<RunTarget>,
+    pub frame_count: u64,
+    // records every executed instruction when set
+    pub trace: Option<TraceRecorder>,
+    // how long run spends on a frame, timers tick once per frame
+    pub frame_duration: Duration,
+}
+
+impl Runner {
+    pub fn new(chip: ChipAte, cycles_per_frame: usize, fault_policy: FaultPolicy) -> Self {
+        Runner {
+            chip,
+            cycles_per_frame,
+            fault_policy,
+            stopped: None,
+            paused: false,
+            pause_reason: None,
+            run_to: None,
+            frame_count: 0,
+            trace: None,
+            frame_duration: FRAME_DURATION,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stopped.is_none() && !self.paused
+    }
+
+    // what the frontend should show about the execution state, if anything
+    pub fn status(&self) -> Option<&str> {
+        if let Some(stopped) = &self.stopped {
+            Some(stopped)
+        } else if self.paused {
+            Some(self.pause_reason.as_deref().unwrap_or("paused"))
+        } else {
+            None
+        }
+    }
+
+    pub fn run_frame(&mut self) -> FrameStatus {
+        self.frame_count += 1;
+        if !self.is_running() {
+            return FrameStatus::Running;
+        }
+
+        for _ in 0..self.cycles_per_frame {
+            match self.cycle() {
+                CycleStatus::WaitingForKey | CycleStatus::WaitingForVblank => break,
+                CycleStatus::Exited => return FrameStatus::Exited,
+                CycleStatus::Fault { .. } | CycleStatus::Break { .. } if !self.is_running() => {
+                    return FrameStatus::Running
+                }
+                _ => {}
+            }
+            if self.reached_run_target() {
+                break;
+            }
+        }
+
+        self.chip.update_timers();
+        FrameStatus::Running
+    }
+
+    // one instruction, applying the fault policy and pausing on breakpoints
+    fn cycle(&mut self) -> CycleStatus {
+        let entry = self
+            .trace
+            .as_ref()
+            .and_then(|trace| TraceEntry::capture(&self.chip, trace.cycles));
+        let status = self.chip.cycle();
+        if let Some(entry) = entry {
+            self.record_trace(&entry, &status);
+        }
+        if let CycleStatus::Break { pc, reason } = status {
+            info!("Break at {:#05X}: {}", pc, reason);
+            self.paused = true;
+            self.run_to = None;
+            self.pause_reason = Some(format!("paused at {:#05X}: {}", pc, reason));
+        }
+        if let CycleStatus::Fault { pc, error } = status {
+            if self.fault_policy == FaultPolicy::Log {
+                warn!("Fault at {:#05X}: {}", pc, error);
+            }
+            if self.fault_policy.stops_on(&error) {
+                error!("Stopped on fault at {:#05X}: {}", pc, error);
+                if self.fault_policy == FaultPolicy::Debug {
+                    // back on the faulting instruction so the debugger shows
+                    // it, resuming runs it again
+                    self.chip.pc = pc;
+                    self.paused = true;
+                    self.run_to = None;
+                    self.pause_reason = Some(format!("paused at {:#05X}: {}", pc, error));
+                } else {
+                    self.stopped = Some(format!("halted at {:#05X}: {}", pc, error));
+                }
+            }
+        }
+        status
+    }
+
+    fn record_trace(&mut self, entry: &TraceEntry, status: &CycleStatus) {
+        // breakpoints stop before the instruction, so nothing ran
+        if let CycleStatus::Break {
+            reason: BreakReason::Breakpoint | BreakReason::Condition,
+            ..
+        } = status
+        {
+            return;
+        }
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        if let Err(e) = trace.record(entry) {
+            warn!("Trace stopped: {}", e);
+            self.trace = None;
+        }
+    }
+
+    fn reached_run_target(&mut self) -> bool {
+        let Some(target) = self.run_to else {
+            return false;
+        };
+        if self.chip.pc != target.pc || target.sp.is_some_and(|sp| sp != self.chip.sp) {
+            return false;
+        }
+        self.run_to = None;
+        self.paused = true;
+        true
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if !self.paused {
+            self.chip.breakpoints.resume();
+        }
+        self.run_to = None;
+        self.pause_reason = None;
+    }
+
+    // executes exactly one instruction of a paused machine, timers don't tick
+    pub fn step_instruction(&mut self) -> FrameStatus {
+        if self.stopped.is_some() {
+            return FrameStatus::Running;
+        }
+        self.paused = true;
+        self.pause_reason = None;
+        self.chip.breakpoints.resume();
+        match self.cycle() {
+            CycleStatus::Exited => FrameStatus::Exited,
+            _ => FrameStatus::Running,
+        }
+    }
+
+    // like step_instruction, but runs a whole subroutine when pc is on a Call
+    pub fn step_over(&mut self) -> FrameStatus {
+        let pc = self.chip.pc;
+        match self.chip.peek_opcode(pc).map(Instruction::from_opcode) {
+            Some(Instruction::Call { .. }) => {
+                self.run_until(RunTarget {
+                    pc: pc.wrapping_add(2),
+                    sp: Some(self.chip.sp),
+                });
+                FrameStatus::Running
+            }
+            _ => self.step_instruction(),
+        }
+    }
+
+    // resumes until pc reaches target, then pauses again
+    pub fn run_until(&mut self, target: RunTarget) {
+        if self.stopped.is_some() {
+            return;
+        }
+        self.run_to = Some(target);
+        self.paused = false;
+        self.chip.breakpoints.resume();
+        self.pause_reason = None;
+    }
+
+    // one full frame against a frontend: input, emulation, audio, display
+    pub fn step<F: Frontend + ?Sized>(
+        &mut self,
+        frontend: &mut F,
+    ) -> Result<FrameStatus, Box<dyn Error>> {
+        for event in frontend.poll_input()? {
+            match event {
+                InputEvent::Key { key, pressed } => self.chip.set_key(key, pressed),
+                InputEvent::Quit => return Ok(FrameStatus::Quit),
+            }
+        }
+        let status = self.run_frame();
+        frontend.play_audio(&self.chip);
+        frontend.present(&self.chip, self.status())?;
+        Ok(status)
+    }
+
+    // blocking loop, 60 Hz by default, until the ROM exits, the frontend quits or max_frames pass
+    pub fn run<F: Frontend + ?Sized>(
+        &mut self,
+        frontend: &mut F,
+        max_frames: Option<u64>,
+    ) -> Result<FrameStatus, Box<dyn Error>> {
+        loop {
+            let frame_start = Instant::now();
+            let status = self.step(frontend)?;
+            if status != FrameStatus::Running {
+                return Ok(status);
+            }
+            if max_frames.is_some_and(|max| self.frame_count >= max) {
+                return Ok(FrameStatus::Running);
+            }
+            let elapsed = frame_start.elapsed();
+            if elapsed < self.frame_duration {
+                std::thread::sleep(self.frame_duration - elapsed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a return with nothing on the stack, then v0 := 5
+    const FAULTING: [u8; 4] = [0x00, 0xEE, 0x60, 0x05];
+
+    fn runner(program: &[u8], fault_policy: FaultPolicy) -> Runner {
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(program).unwrap();
+        Runner::new(chip, 2, fault_policy)
+    }
+
+    #[test]
+    fn skip_and_log_carry_on() {
+        for policy in [FaultPolicy::Skip, FaultPolicy::Log] {
+            let mut runner = runner(&FAULTING, policy);
+            assert_eq!(runner.run_frame(), FrameStatus::Running);
+            assert!(runner.is_running());
+            assert_eq!(runner.chip.v[0], 5);
+            assert_eq!(runner.status(), None);
+        }
+    }
+
+    #[test]
+    fn halt_stops() {
+        let mut runner = runner(&FAULTING, FaultPolicy::Halt);
+        runner.run_frame();
+        assert_eq!(runner.chip.v[0], 0);
+        assert_eq!(runner.status(), Some("halted at 0x200: stack underflow"));
+        // a stopped machine doesn't move again
+        runner.run_frame();
+        runner.step_instruction();
+        assert_eq!(runner.chip.v[0], 0);
+    }
+
+    #[test]
+    fn debug_pauses_on_the_fault() {
+        let mut runner = runner(&FAULTING, FaultPolicy::Debug);
+        runner.run_frame();
+        assert!(runner.paused);
+        assert_eq!(runner.stopped, None);
+        assert_eq!(runner.chip.pc, 0x200);
+        assert_eq!(runner.status(), Some("paused at 0x200: stack underflow"));
+    }
+
+    #[test]
+    fn pc_out_of_range_stops_every_policy() {
+        for policy in [
+            FaultPolicy::Halt,
+            FaultPolicy::Skip,
+            FaultPolicy::Log,
+            FaultPolicy::Debug,
+        ] {
+            let mut runner = runner(&[], policy);
+            runner.chip.pc = 0xFFF;
+            runner.run_frame();
+            assert!(!runner.is_running(), "{:?}", policy);
+            assert!(runner.status().unwrap().contains("pc out of range"));
+        }
+    }
+}
@@ -0,0 +1,307 @@
+// Save states: the complete machine state in a small versioned binary format.
+//
+// Layout (little endian):
+//   "C8ST" magic, u16 version, u64 ROM hash, then the machine state in the
+//   order written by ChipAte::save_state. Quirks are not saved, they belong
+//   to the run rather than the machine.
+
+use crate::chip_ate::{ChipAte, MEMORY_SIZE, XO_MEMORY_SIZE};
+use crate::rng::ChipRng;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+// 2: the RNG became counter based, so a version 1 seed and draw count would
+// restore a different sequence
+pub const STATE_VERSION: u16 = 2;
+pub const SLOT_COUNT: u8 = 10;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "{}", e),
+            SaveStateError::BadMagic => write!(f, "not a chipATE save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for a different ROM ({:016x}, loaded {:016x})",
+                found, expected
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Corrupt(what) => write!(f, "save state is corrupt: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> Self {
+        SaveStateError::Io(e)
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn fill(&mut self, out: &mut [u8]) -> Result<(), SaveStateError> {
+        out.copy_from_slice(self.bytes(out.len())?);
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+impl ChipAte {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + self.display.len() + 256);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+
+        out.push(self.xo_chip as u8);
+        out.push(self.hires as u8);
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        for entry in self.stack {
+            out.extend_from_slice(&entry.to_le_bytes());
+        }
+        out.push(self.sp);
+        out.extend_from_slice(&self.display);
+        out.push(self.plane);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.rpl);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.keypad);
+        // 0xFF marks no pending key press, keys only go up to 0xF
+        out.push(self.pressed_key.unwrap_or(0xFF));
+        out.extend_from_slice(&self.rng.seed().to_le_bytes());
+        out.extend_from_slice(&self.rng.draws().to_le_bytes());
+        out
+    }
+
+    // Restores a state made by save_state. Nothing changes unless the whole
+    // state is valid and was saved from the same ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader { data };
+        if reader.bytes(4)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.u64()?;
+        if rom_hash != self.rom_hash {
+            return Err(SaveStateError::RomMismatch {
+                expected: self.rom_hash,
+                found: rom_hash,
+            });
+        }
+
+        let mut state = self.clone();
+        state.xo_chip = reader.u8()? != 0;
+        state.hires = reader.u8()? != 0;
+        let memory_size = reader.u32()? as usize;
+        if memory_size != MEMORY_SIZE && memory_size != XO_MEMORY_SIZE {
+            return Err(SaveStateError::Corrupt("memory size"));
+        }
+        state.memory = reader.bytes(memory_size)?.to_vec();
+        reader.fill(&mut state.v)?;
+        state.i = reader.u16()?;
+        state.pc = reader.u16()?;
+        for entry in state.stack.iter_mut() {
+            *entry = reader.u16()?;
+        }
+        state.sp = reader.u8()?;
+        if state.sp as usize > state.stack.len() {
+            return Err(SaveStateError::Corrupt("stack pointer"));
+        }
+        reader.fill(&mut state.display)?;
+        state.plane = reader.u8()?;
+        reader.fill(&mut state.audio_pattern)?;
+        state.pitch = reader.u8()?;
+        reader.fill(&mut state.rpl)?;
+        state.delay_timer = reader.u8()?;
+        state.sound_timer = reader.u8()?;
+        reader.fill(&mut state.keypad)?;
+        state.pressed_key = match reader.u8()? {
+            0xFF => None,
+            key => Some(key & 0xF),
+        };
+        let seed = reader.u64()?;
+        let draws = reader.u64()?;
+        state.rng = ChipRng::restore(seed, draws);
+
+        *self = state;
+        Ok(())
+    }
+}
+
+// states live in <dir>/<rom hash>/slot<n>.c8s so each ROM has its own slots
+pub fn slot_path(dir: &Path, rom_hash: u64, slot: u8) -> PathBuf {
+    dir.join(format!("{:016x}", rom_hash))
+        .join(format!("slot{}.c8s", slot))
+}
+
+pub fn save_slot(chip: &ChipAte, dir: &Path, slot: u8) -> Result<PathBuf, SaveStateError> {
+    let path = slot_path(dir, chip.rom_hash, slot);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, chip.save_state())?;
+    Ok(path)
+}
+
+pub fn load_slot(chip: &mut ChipAte, dir: &Path, slot: u8) -> Result<PathBuf, SaveStateError> {
+    let path = slot_path(dir, chip.rom_hash, slot);
+    let data = std::fs::read(&path)?;
+    chip.load_state(&data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_ate::rom_hash;
+
+    // v0 := random 0xFF, jump back
+    const RANDOM_LOOP: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    fn chip() -> ChipAte {
+        let mut chip = ChipAte::new();
+        chip.load_rom_bytes(&RANDOM_LOOP).unwrap();
+        chip.seed_rng(42);
+        chip
+    }
+
+    fn randoms(chip: &mut ChipAte, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                chip.cycle();
+                chip.cycle();
+                chip.v[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut chip = chip();
+        randoms(&mut chip, 10);
+        chip.hires = true;
+        chip.display[5] = 1;
+        chip.set_key(0xA, true);
+        chip.set_sound_timer(9);
+        let state = chip.save_state();
+
+        let mut restored = self::chip();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pressed_key, Some(0xA));
+    }
+
+    #[test]
+    fn random_numbers_continue_after_a_load() {
+        let mut chip = chip();
+        randoms(&mut chip, 10);
+        let state = chip.save_state();
+        let expected = randoms(&mut chip, 20);
+
+        chip.load_state(&state).unwrap();
+        assert_eq!(randoms(&mut chip, 20), expected);
+        // also in a fresh machine with a different seed
+        let mut other = self::chip();
+        other.seed_rng(7);
+        other.load_state(&state).unwrap();
+        assert_eq!(randoms(&mut other, 20), expected);
+    }
+
+    #[test]
+    fn rejects_another_roms_state() {
+        let state = chip().save_state();
+        let mut other = ChipAte::new();
+        other.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::RomMismatch { expected, found })
+                if expected == other.rom_hash && found == rom_hash(&RANDOM_LOOP)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut state = chip().save_state();
+        let mut chip = chip();
+        state[0] = b'X';
+        assert!(matches!(
+            chip.load_state(&state),
+            Err(SaveStateError::BadMagic)
+        ));
+
+        state[0] = MAGIC[0];
+        state[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert!(matches!(
+            chip.load_state(&state),
+            Err(SaveStateError::UnsupportedVersion(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_states_without_changing_anything() {
+        let mut chip = chip();
+        let state = chip.save_state();
+        randoms(&mut chip, 3);
+        let before = chip.save_state();
+        for len in [0, 3, 10, 100, state.len() - 1] {
+            assert!(matches!(
+                chip.load_state(&state[..len]),
+                Err(SaveStateError::Truncated)
+            ));
+            assert_eq!(chip.save_state(), before);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Screenshots of the framebuffer as PNG, PBM or the text format expected
+// screens use. The format comes from the file extension.
+
+use crate::chip_ate::ChipAte;
+use crate::headless::Screen;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub type Rgb = [u8; 3];
+
+// indexed by the plane bits of a pixel: off, plane 1, plane 2, both planes;
+// the colours the SDL window uses
+pub const DEFAULT_PALETTE: [Rgb; 4] = [[0, 0, 0], [255, 255, 255], [255, 85, 85], [255, 255, 85]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    // scaled up and in colour
+    Png,
+    // plain (P1) bitmap, any lit plane is black
+    Pbm,
+    // the '.'/'#' text of headless::Screen
+    Text,
+}
+
+impl ImageFormat {
+    pub const EXTENSIONS: [&'static str; 3] = ["png", "pbm", "txt"];
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            "txt" => Some(ImageFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+// every pixel becomes a scale x scale block
+pub fn write_png(
+    screen: &Screen,
+    palette: &[Rgb; 4],
+    scale: u32,
+    out: impl Write,
+) -> Result<(), png::EncodingError> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (screen.width * scale, screen.height * scale);
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.concat());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&scale_pixels(screen, scale))
+}
+
+// palette indices of the screen with every pixel a scale x scale block
+pub fn scale_pixels(screen: &Screen, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(screen.cells.len() * scale * scale);
+    for row in screen.cells.chunks(screen.width) {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|&cell| std::iter::repeat_n(cell & 3, scale))
+            .collect();
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+    pixels
+}
+
+// four comma separated RGB hex colours, e.g. "000000,ffffff,ff5555,ffff55",
+// for off, plane 1, plane 2 and both planes
+pub fn parse_palette(spec: &str) -> Option<[Rgb; 4]> {
+    let colours: Vec<Rgb> = spec
+        .split(',')
+        .map(|colour| parse_rgb(colour.trim()))
+        .collect::<Option<_>>()?;
+    colours.try_into().ok()
+}
+
+// "ff8800" or "#ff8800"
+pub fn parse_rgb(text: &str) -> Option<Rgb> {
+    let digits = text.strip_prefix('#').unwrap_or(text);
+    if digits.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+pub fn pbm(screen: &Screen) -> String {
+    let mut out = format!("P1\n{} {}\n", screen.width, screen.height);
+    for row in screen.cells.chunks(screen.width) {
+        let bits: Vec<&str> = row
+            .iter()
+            .map(|&cell| if cell != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&bits.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+// writes the current screen to path in the format its extension names
+pub fn save(
+    chip: &ChipAte,
+    path: &Path,
+    palette: &[Rgb; 4],
+    scale: u32,
+) -> Result<(), Box<dyn Error>> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        format!(
+            "{}: screenshots need a .{} extension",
+            path.display(),
+            ImageFormat::EXTENSIONS.join(", .")
+        )
+    })?;
+    let screen = Screen::capture(chip);
+    match format {
+        ImageFormat::Png => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_png(&screen, palette, scale, &mut out)?;
+            out.flush()?;
+        }
+        ImageFormat::Pbm => std::fs::write(path, pbm(&screen))?,
+        ImageFormat::Text => std::fs::write(path, screen.to_string())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use crate::audio::{self, AudioBackend, AudioKind, AudioSettings};
+use crate::chip_ate::ChipAte;
+use crate::frontend::{Frontend, InputEvent};
+use crate::keymap::Keymap;
+use crate::screenshot::{Rgb, DEFAULT_PALETTE};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::{EventPump, Sdl};
+use std::error::Error;
+
+const FLASH_BACKGROUND: Color = Color::RGB(64, 64, 64);
+
+// Windowed frontend. Runs under SDL_VIDEODRIVER=dummy as well, so it can be
+// exercised in headless CI.
+pub struct SdlFrontend {
+    canvas: WindowCanvas,
+    event_pump: EventPump,
+    audio: Box<dyn AudioBackend>,
+    pub keymap: Keymap,
+    // indexed by the plane bits of a pixel: off, plane 1, plane 2, both planes
+    pub palette: [Rgb; 4],
+    _sdl: Sdl,
+}
+
+impl SdlFrontend {
+    // scale is the size of a lores pixel, hires gets half that
+    pub fn new(audio_settings: &AudioSettings, scale: u32) -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+        let window = video
+            .window("Chip Ate", 64 * scale, 32 * scale)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let event_pump = sdl.event_pump()?;
+
+        // SDL only allows one context, so the SDL audio backend has to share it
+        let audio: Box<dyn AudioBackend> = match audio_settings.kind {
+            AudioKind::Sdl => Box::new(audio::sdl_audio::SdlAudio::new(&sdl, audio_settings)?),
+            _ => audio::create_backend(audio_settings)?,
+        };
+
+        Ok(SdlFrontend {
+            canvas,
+            event_pump,
+            audio,
+            keymap: Keymap::default(),
+            palette: DEFAULT_PALETTE,
+            _sdl: sdl,
+        })
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn present(&mut self, chip: &ChipAte, status: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let title = match status {
+            Some(status) => format!("Chip Ate - {}", status),
+            None => "Chip Ate".to_string(),
+        };
+        self.canvas.window_mut().set_title(&title)?;
+
+        let (width, height) = (chip.width() as u32, chip.height() as u32);
+        let (window_width, window_height) = self.canvas.output_size()?;
+        // largest integer scale that fits, centred in the window
+        let scale = (window_width / width).min(window_height / height).max(1);
+        let x_offset = (window_width.saturating_sub(width * scale) / 2) as i32;
+        let y_offset = (window_height.saturating_sub(height * scale) / 2) as i32;
+
+        let background = if self.audio.flashing() {
+            FLASH_BACKGROUND
+        } else {
+            rgb(self.palette[0])
+        };
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.set_draw_color(background);
+        self.canvas
+            .fill_rect(Rect::new(x_offset, y_offset, width * scale, height * scale))?;
+        for (index, &pixel) in chip.frame().iter().enumerate() {
+            let pixel = pixel as usize & 0x3;
+            if pixel == 0 {
+                continue;
+            }
+            let x = (index as u32 % width) * scale;
+            let y = (index as u32 / width) * scale;
+            self.canvas.set_draw_color(rgb(self.palette[pixel]));
+            self.canvas.fill_rect(Rect::new(
+                x_offset + x as i32,
+                y_offset + y as i32,
+                scale,
+                scale,
+            ))?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Result<Vec<InputEvent>, Box<dyn Error>> {
+        let mut events = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => events.push(InputEvent::Quit),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(key) = map_keycode(&self.keymap, keycode) {
+                        events.push(InputEvent::Key { key, pressed: true });
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = map_keycode(&self.keymap, keycode) {
+                        events.push(InputEvent::Key {
+                            key,
+                            pressed: false,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+
+    fn play_audio(&mut self, chip: &ChipAte) {
+        self.audio.update(chip);
+    }
+}
+
+fn map_keycode(keymap: &Keymap, keycode: Keycode) -> Option<u8> {
+    let name = keycode.name();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => keymap.keypad_for_char(c),
+        _ => None,
+    }
+}
+
+fn rgb([r, g, b]: Rgb) -> Color {
+    Color::RGB(r, g, b)
+}
@@ -0,0 +1,237 @@
+// Colour themes for the terminal display.
+//
+// Themes are defined in RGB and brought down to what the terminal can show:
+// truecolor as is, otherwise the nearest entry of the xterm 256-colour cube or
+// of the 16 ANSI colours. Palettes are indexed by the plane bits of a pixel
+// (off, plane 1, plane 2, both planes), so XO-CHIP's second plane gets its
+// own colours in every theme.
+
+use crate::screenshot::{Rgb, DEFAULT_PALETTE};
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    // whatever detect finds
+    #[default]
+    Auto,
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    pub const NAMES: [&'static str; 4] = ["auto", "truecolor", "256", "16"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Some(ColorDepth::Auto),
+            "truecolor" | "24bit" => Some(ColorDepth::TrueColor),
+            "256" => Some(ColorDepth::Ansi256),
+            "16" => Some(ColorDepth::Ansi16),
+            _ => None,
+        }
+    }
+
+    // what the environment says the terminal supports
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    pub fn colour(self, rgb: Rgb) -> Color {
+        match self {
+            ColorDepth::Auto => ColorDepth::detect().colour(rgb),
+            ColorDepth::TrueColor => Color::Rgb(rgb[0], rgb[1], rgb[2]),
+            ColorDepth::Ansi256 => Color::Indexed(nearest_256(rgb)),
+            ColorDepth::Ansi16 => nearest_16(rgb),
+        }
+    }
+}
+
+// the character drawn for a lit pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Glyph {
+    #[default]
+    Solid,
+    Dotted,
+    Rounded,
+}
+
+impl Glyph {
+    pub const NAMES: [&'static str; 3] = ["solid", "dotted", "rounded"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "solid" => Some(Glyph::Solid),
+            "dotted" => Some(Glyph::Dotted),
+            "rounded" => Some(Glyph::Rounded),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Glyph::Solid => "█",
+            Glyph::Dotted => "⣿",
+            Glyph::Rounded => "●",
+        }
+    }
+}
+
+// A theme as RGB colours, before the terminal's colour depth is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scheme {
+    pub palette: [Rgb; 4],
+    pub border: Rgb,
+    // leave unlit pixels to the terminal's own background
+    pub keep_background: bool,
+}
+
+impl Scheme {
+    pub const NAMES: [&'static str; 5] = ["default", "amber", "green", "lcd", "high-contrast"];
+
+    // the SDL window's colours on the terminal's background
+    pub const DEFAULT: Scheme = Scheme {
+        palette: DEFAULT_PALETTE,
+        border: [255, 255, 255],
+        keep_background: true,
+    };
+
+    pub const AMBER: Scheme = Scheme {
+        palette: [[20, 12, 0], [255, 176, 0], [122, 82, 0], [255, 216, 102]],
+        border: [255, 176, 0],
+        keep_background: false,
+    };
+
+    // P1 green phosphor
+    pub const GREEN: Scheme = Scheme {
+        palette: [[0, 18, 0], [51, 255, 102], [26, 127, 51], [179, 255, 198]],
+        border: [51, 255, 102],
+        keep_background: false,
+    };
+
+    // the four shades of a Game Boy screen
+    pub const LCD: Scheme = Scheme {
+        palette: [[155, 188, 15], [15, 56, 15], [139, 172, 15], [48, 98, 48]],
+        border: [15, 56, 15],
+        keep_background: false,
+    };
+
+    pub const HIGH_CONTRAST: Scheme = Scheme {
+        palette: [[0, 0, 0], [255, 255, 255], [0, 255, 255], [255, 255, 0]],
+        border: [255, 255, 255],
+        keep_background: false,
+    };
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Scheme::DEFAULT),
+            "amber" => Some(Scheme::AMBER),
+            "green" | "phosphor" | "green-phosphor" => Some(Scheme::GREEN),
+            "lcd" | "gameboy" => Some(Scheme::LCD),
+            "high-contrast" | "contrast" => Some(Scheme::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    pub fn theme(&self, depth: ColorDepth, glyph: Glyph) -> Theme {
+        let mut palette = self.palette.map(|rgb| depth.colour(rgb));
+        if self.keep_background {
+            palette[0] = Color::Reset;
+        }
+        Theme {
+            palette,
+            border: depth.colour(self.border),
+            glyph,
+        }
+    }
+}
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Scheme::DEFAULT
+    }
+}
+
+// Colours and glyph of the terminal display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    // palette[0] is painted behind the display, Reset leaves it alone
+    pub palette: [Color; 4],
+    pub border: Color,
+    pub glyph: Glyph,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            palette: [Color::Reset, Color::White, Color::LightRed, Color::Yellow],
+            border: Color::White,
+            glyph: Glyph::Solid,
+        }
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+// levels of the 6x6x6 cube at indices 16-231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn nearest_256(rgb: Rgb) -> u8 {
+    let level = |value: u8| {
+        (0..6)
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - value as i32).abs())
+            .unwrap_or(0)
+    };
+    let (r, g, b) = (level(rgb[0]), level(rgb[1]), level(rgb[2]));
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let cube_index = 16 + 36 * r + 6 * g + b;
+    // the grey ramp at 232-255 runs from 8 to 238 in steps of 10
+    let average = rgb.iter().map(|&value| value as u32).sum::<u32>() / 3;
+    let grey_step = (average.saturating_sub(3) / 10).min(23);
+    let grey = (8 + grey_step * 10) as u8;
+    if distance(rgb, [grey; 3]) < distance(rgb, cube) {
+        232 + grey_step as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+// xterm's defaults for the 16 ANSI colours
+const ANSI_16: [(Color, Rgb); 16] = [
+    (Color::Black, [0, 0, 0]),
+    (Color::Red, [205, 0, 0]),
+    (Color::Green, [0, 205, 0]),
+    (Color::Yellow, [205, 205, 0]),
+    (Color::Blue, [0, 0, 238]),
+    (Color::Magenta, [205, 0, 205]),
+    (Color::Cyan, [0, 205, 205]),
+    (Color::Gray, [229, 229, 229]),
+    (Color::DarkGray, [127, 127, 127]),
+    (Color::LightRed, [255, 0, 0]),
+    (Color::LightGreen, [0, 255, 0]),
+    (Color::LightYellow, [255, 255, 0]),
+    (Color::LightBlue, [92, 92, 255]),
+    (Color::LightMagenta, [255, 0, 255]),
+    (Color::LightCyan, [0, 255, 255]),
+    (Color::White, [255, 255, 255]),
+];
+
+fn nearest_16(rgb: Rgb) -> Color {
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map_or(Color::White, |&(colour, _)| colour)
+}
@@ -0,0 +1,239 @@
+// Per-instruction execution traces, for diffing against other emulators.
+//
+// One line per executed instruction with the machine state before it ran:
+//
+//   # chipATE trace v1
+//   0 pc=0200 op=00E0 v=00000000000000000000000000000000 i=0000 sp=00 dt=00 st=00 ; CLS
+//
+// The first field is the number of instructions executed before this one,
+// then key=value fields in hex and the mnemonic after ';'. Comparisons skip
+// the mnemonic and '#' lines and ignore hex case, so another emulator only
+// has to print the same fields to be diffed against.
+
+use crate::breakpoints::parse_address;
+use crate::chip_ate::ChipAte;
+use crate::opcodes::Instruction;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+pub const TRACE_HEADER: &str = "# chipATE trace v1";
+
+// The machine state just before an instruction runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    // None when pc doesn't point at a whole opcode
+    pub fn capture(chip: &ChipAte, cycle: u64) -> Option<Self> {
+        Some(TraceEntry {
+            cycle,
+            pc: chip.pc,
+            opcode: chip.peek_opcode(chip.pc)?,
+            v: chip.v,
+            i: chip.i,
+            sp: chip.sp,
+            delay_timer: chip.delay_timer,
+            sound_timer: chip.sound_timer,
+        })
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v: String = self
+            .v
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+        write!(
+            f,
+            "{} pc={:04X} op={:04X} v={} i={:04X} sp={:02X} dt={:02X} st={:02X} ; {}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            v,
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            Instruction::from_opcode(self.opcode)
+        )
+    }
+}
+
+// Writes a trace line for every instruction the Runner executes. Cycles are
+// counted whether or not they pass the address filter, so filtered traces
+// still line up.
+pub struct TraceRecorder {
+    out: Box<dyn Write>,
+    // inclusive pc ranges to record, everything when empty
+    ranges: Vec<(u16, u16)>,
+    pub cycles: u64,
+}
+
+impl fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceRecorder")
+            .field("ranges", &self.ranges)
+            .field("cycles", &self.cycles)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TraceRecorder {
+    pub fn new(mut out: Box<dyn Write>) -> io::Result<Self> {
+        writeln!(out, "{}", TRACE_HEADER)?;
+        Ok(TraceRecorder {
+            out,
+            ranges: Vec::new(),
+            cycles: 0,
+        })
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn add_range(&mut self, start: u16, end: u16) {
+        self.ranges.push((start, end));
+    }
+
+    // call after the entry's instruction has run
+    pub fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        self.cycles += 1;
+        let wanted = self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&entry.pc));
+        if wanted {
+            writeln!(self.out, "{}", entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// "0x200-0x2ff" or a single address, hex with or without 0x
+pub fn parse_range(spec: &str) -> Option<(u16, u16)> {
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(spec)?, parse_address(spec)?),
+    };
+    (start <= end).then_some((start, end))
+}
+
+// Where two traces first disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // 1-based line number in each file
+    pub left_line: usize,
+    pub right_line: usize,
+    // None when that trace ended first
+    pub left: Option<String>,
+    pub right: Option<String>,
+    // names of the fields that differ, empty when a trace ended
+    pub fields: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fields.is_empty() {
+            writeln!(f, "traces diverge: one ends early")?;
+        } else {
+            writeln!(f, "traces diverge in {}", self.fields.join(", "))?;
+        }
+        let side = |line: &Option<String>| line.clone().unwrap_or_else(|| "<end of trace>".into());
+        writeln!(f, "< {}: {}", self.left_line, side(&self.left))?;
+        write!(f, "> {}: {}", self.right_line, side(&self.right))
+    }
+}
+
+// the next trace line and its number, skipping blanks and comments
+fn next_line(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    number: &mut usize,
+) -> io::Result<Option<String>> {
+    for line in lines {
+        let line = line?;
+        *number += 1;
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            return Ok(Some(trimmed.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+// (name, value) pairs of a line, the leading cycle count is named "cycle"
+fn fields(line: &str) -> Vec<(String, String)> {
+    let state = line.split(';').next().unwrap_or("");
+    state
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, field)| match field.split_once('=') {
+            Some((name, value)) => (name.to_ascii_lowercase(), value.to_ascii_uppercase()),
+            None if index == 0 => ("cycle".to_string(), field.to_string()),
+            None => (field.to_ascii_lowercase(), String::new()),
+        })
+        .collect()
+}
+
+fn differing_fields(left: &str, right: &str) -> Vec<String> {
+    let (left, right) = (fields(left), fields(right));
+    let mut names: Vec<String> = Vec::new();
+    for (name, value) in &left {
+        let other = right.iter().find(|(other, _)| other == name);
+        if other.is_none_or(|(_, other)| other != value) {
+            names.push(name.clone());
+        }
+    }
+    for (name, _) in &right {
+        if !left.iter().any(|(other, _)| other == name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+// the first line where the traces disagree, None when they match
+pub fn first_divergence(left: impl BufRead, right: impl BufRead) -> io::Result<Option<Divergence>> {
+    let (mut left_lines, mut right_lines) = (left.lines(), right.lines());
+    let (mut left_number, mut right_number) = (0, 0);
+    loop {
+        let left = next_line(&mut left_lines, &mut left_number)?;
+        let right = next_line(&mut right_lines, &mut right_number)?;
+        let fields = match (&left, &right) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) => {
+                let fields = differing_fields(l, r);
+                if fields.is_empty() {
+                    continue;
+                }
+                fields
+            }
+            _ => Vec::new(),
+        };
+        return Ok(Some(Divergence {
+            left_line: left_number,
+            right_line: right_number,
+            left,
+            right,
+            fields,
+        }));
+    }
+}
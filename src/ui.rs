@@ -1,59 +1,200 @@
+use crate::audio::AudioBackend;
+use crate::chip_ate::ChipAte;
+use crate::debugger::{DebuggerView, PANEL_WIDTH};
+use crate::frontend::{Frontend, InputEvent};
+use crate::keymap::Keymap;
+use crate::render::{Display, RenderMode};
+use crate::theme::Theme;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::{
     backend::Backend,
-    layout::Rect,
-    style::{Color, Style, Stylize},
-    widgets::{canvas::Canvas, Block, Borders},
+    layout::{Alignment, Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Wrap},
     Terminal,
 };
+use std::error::Error;
 use std::io;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
 pub struct UI<B: Backend> {
     pub terminal: Terminal<B>,
+    pub audio: Box<dyn AudioBackend>,
+    // shown in the title bar, e.g. why emulation stopped
+    pub status: Option<String>,
+    // inverts the border while a visual audio backend is sounding
+    pub flash: bool,
+    // short-lived message like "saved slot 1", shown when there is no status
+    notice: Option<(String, Instant)>,
+    pub debugger: DebuggerView,
+    pub keymap: Keymap,
+    pub theme: Theme,
+    pub render_mode: RenderMode,
 }
 
+const NOTICE_DURATION: Duration = Duration::from_secs(2);
+
 impl<B: Backend> UI<B> {
-    pub fn new(terminal: Terminal<B>) -> Self {
-        UI { terminal }
+    pub fn new(terminal: Terminal<B>, audio: Box<dyn AudioBackend>) -> Self {
+        UI {
+            terminal,
+            audio,
+            status: None,
+            flash: false,
+            notice: None,
+            debugger: DebuggerView::default(),
+            keymap: Keymap::default(),
+            theme: Theme::default(),
+            render_mode: RenderMode::default(),
+        }
     }
 
-    pub fn render(&mut self, display: &[u8; 64 * 32]) -> Result<(), io::Error> {
+    pub fn render(&mut self, chip: &ChipAte) -> Result<(), io::Error> {
+        let (display, width, height) = (chip.frame(), chip.width(), chip.height());
+        if self
+            .notice
+            .as_ref()
+            .is_some_and(|(_, shown)| shown.elapsed() > NOTICE_DURATION)
+        {
+            self.notice = None;
+        }
+        let title = match (&self.status, &self.notice) {
+            (Some(status), _) => format!("Chip Ate - {}", status),
+            (None, Some((notice, _))) => format!("Chip Ate - {}", notice),
+            (None, None) => "Chip Ate".to_string(),
+        };
+        let theme = self.theme;
+        let border_style = if self.flash {
+            Style::default().fg(Color::Black).bg(theme.border)
+        } else {
+            Style::default().fg(theme.border)
+        };
+        let debugger = &self.debugger;
+        let render_mode = self.render_mode;
         self.terminal.draw(|frame| {
-            let size = frame.area();
-
-            let canvas = Canvas::default()
-                .block(
-                    Block::default()
-                        .title("Chip Ate")
-                        .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::White)),
-                )
-                .marker(ratatui::symbols::Marker::Block)
-                .paint(|ctx| {
-                    for y in 0..32 {
-                        for x in 0..64 {
-                            if display[y * 64 + x] == 1 {
-                                ctx.print(x as f64, (32 - y) as f64, "█".fg(Color::White));
-                            }
-                        }
-                    }
-                })
-                .x_bounds([0.0, 64.0])
-                .y_bounds([0.0, 32.0]);
-
-            let display_width = 64.min(size.width);
-            let display_height = (32 + 2).min(size.height);
-            let x_offset = (size.width.saturating_sub(display_width)) / 2;
-            let y_offset = (size.height.saturating_sub(display_height)) / 2;
+            // the debugger panel takes the right hand side, the display is
+            // centred in whatever is left
+            let size = if debugger.visible {
+                let [display_area, panel_area] =
+                    Layout::horizontal([Constraint::Min(0), Constraint::Length(PANEL_WIDTH)])
+                        .areas(frame.area());
+                debugger.render(frame, panel_area, chip);
+                display_area
+            } else {
+                frame.area()
+            };
+
+            let block = Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(border_style);
+            // the border takes a cell on every side
+            let inside = (size.width.saturating_sub(2), size.height.saturating_sub(2));
+            let Some((mode, scale)) = render_mode.fit(width, height, inside) else {
+                // a cropped picture is easy to mistake for a broken ROM
+                let (columns, rows) = render_mode.cells(width, height);
+                let message = Paragraph::new(vec![
+                    Line::from("Terminal too small"),
+                    Line::from(format!(
+                        "need {}x{}, have {}x{}",
+                        columns + 2,
+                        rows + 2,
+                        size.width,
+                        size.height
+                    )),
+                ])
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: true });
+                let inner = block.inner(size);
+                let lines = inner.height.min(2);
+                let message_area = Rect::new(
+                    inner.x,
+                    inner.y + (inner.height - lines) / 2,
+                    inner.width,
+                    lines,
+                );
+                frame.render_widget(block, size);
+                frame.render_widget(message, message_area);
+                return;
+            };
+            let (columns, rows) = mode.cells(width * scale, height * scale);
+            let display_width = columns + 2;
+            let display_height = rows + 2;
+            let x_offset = size.x + (size.width - display_width) / 2;
+            let y_offset = size.y + (size.height - display_height) / 2;
             let display_area = Rect::new(x_offset, y_offset, display_width, display_height);
 
-            frame.render_widget(canvas, display_area);
+            let inner = block.inner(display_area);
+            frame.render_widget(block, display_area);
+            frame.render_widget(
+                Display {
+                    pixels: display,
+                    width,
+                    height,
+                    theme: &theme,
+                    mode,
+                    scale,
+                },
+                inner,
+            );
         })?;
         Ok(())
     }
 
+    // redraws straight away at the new size instead of on the next frame
+    pub fn resize(&mut self, chip: &ChipAte) -> Result<(), io::Error> {
+        self.terminal.autoresize()?;
+        self.render(chip)
+    }
+
+    pub fn notify(&mut self, message: String) {
+        self.notice = Some((message, Instant::now()));
+    }
+
     pub fn cleanup(&mut self) -> Result<(), io::Error> {
         self.terminal.clear()?;
         Ok(())
     }
 }
+
+impl<B: Backend> Frontend for UI<B> {
+    fn present(&mut self, chip: &ChipAte, status: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.status = status.map(str::to_string);
+        self.render(chip)?;
+        Ok(())
+    }
+
+    // reads crossterm events synchronously, for driving the UI without the
+    // async event handler the binary uses
+    fn poll_input(&mut self) -> Result<Vec<InputEvent>, Box<dyn Error>> {
+        let mut events = Vec::new();
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key_event) = event::read()? {
+                let pressed = match key_event.kind {
+                    KeyEventKind::Press => true,
+                    KeyEventKind::Release => false,
+                    KeyEventKind::Repeat => continue,
+                };
+                if key_event.code == KeyCode::Esc {
+                    events.push(InputEvent::Quit);
+                } else if let Some(key) = map_key(&self.keymap, key_event.code) {
+                    events.push(InputEvent::Key { key, pressed });
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn play_audio(&mut self, chip: &ChipAte) {
+        self.audio.update(chip);
+        self.flash = self.audio.flashing();
+    }
+}
+
+pub fn map_key(keymap: &Keymap, key: KeyCode) -> Option<u8> {
+    match key {
+        KeyCode::Char(c) => keymap.keypad_for_char(c),
+        _ => None,
+    }
+}